@@ -33,6 +33,26 @@ impl Aabb {
         true
     }
 
+    /// Slab test with the reciprocal of the ray direction computed up front.
+    #[inline]
+    pub fn hit_inv(&self, origin: Vec3, inv_direction: Vec3, t_min: f32, t_max: f32) -> bool {
+        let (mut t_min, mut t_max) = (t_min, t_max);
+
+        for axis in 0..3 {
+            let t0 = (self.min[axis] - origin[axis]) * inv_direction[axis];
+            let t1 = (self.max[axis] - origin[axis]) * inv_direction[axis];
+
+            t_min = ffmax(ffmin(t0, t1), t_min);
+            t_max = ffmin(ffmax(t0, t1), t_max);
+
+            if t_max <= t_min {
+                return false;
+            }
+        }
+
+        true
+    }
+
     pub fn merge(a: Aabb, b: Aabb) -> Aabb {
         let small = Vec3::new(
             a.min.x.min(b.min.x),
@@ -50,6 +70,55 @@ impl Aabb {
             max: big,
         }
     }
+
+    pub fn centroid(&self) -> Vec3 {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let d = self.max - self.min;
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    pub fn from_points(points: &[Vec3]) -> Aabb {
+        let mut min = Vec3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = Vec3::new(f32::MIN, f32::MIN, f32::MIN);
+        for p in points {
+            min = Vec3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+            max = Vec3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+        }
+
+        Aabb { min, max }
+    }
+
+    /// Grows every axis thinner than `delta` to `delta`.
+    pub fn padded(self, delta: f32) -> Aabb {
+        let mut min = self.min;
+        let mut max = self.max;
+        if max.x - min.x < delta {
+            min.x -= delta / 2.0;
+            max.x += delta / 2.0;
+        }
+        if max.y - min.y < delta {
+            min.y -= delta / 2.0;
+            max.y += delta / 2.0;
+        }
+        if max.z - min.z < delta {
+            min.z -= delta / 2.0;
+            max.z += delta / 2.0;
+        }
+
+        Aabb { min, max }
+    }
+
+    /// Moves every side out by `delta`.
+    pub fn expanded(self, delta: f32) -> Aabb {
+        let delta = Vec3::new(delta, delta, delta);
+        Aabb {
+            min: self.min - delta,
+            max: self.max + delta,
+        }
+    }
 }
 
 #[inline]
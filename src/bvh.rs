@@ -1,50 +1,425 @@
 use crate::aabb::Aabb;
 use crate::hit::{HitRecord, Hitable, HitableHandle};
 use crate::ray::Ray;
-use crate::util::local_rng;
-use rand::Rng;
-use std::cmp::Ordering;
+use crate::vec3::Vec3;
 use std::sync::Arc;
 
+/// Cost of visiting an interior node relative to intersecting one primitive.
+const TRAVERSAL_COST: f32 = 1.0;
+
+/// Below this depth the builder splits at the median so the tree fits the traversal stack.
+const MAX_SAH_DEPTH: usize = 32;
+
+/// Room for the far children pending during traversal, enough for any tree the builder makes.
+const STACK_SIZE: usize = 64;
+
+#[derive(Debug, Copy, Clone)]
+pub struct BvhOptions {
+    /// Nodes with up to this many primitives become leaves when splitting doesn't pay off.
+    pub max_leaf_size: usize,
+    /// Number of buckets the centroids are binned into along each axis.
+    pub bins: usize,
+}
+
+impl Default for BvhOptions {
+    fn default() -> Self {
+        BvhOptions {
+            max_leaf_size: 4,
+            bins: 12,
+        }
+    }
+}
+
+pub enum BvhChildren {
+    Leaf(Vec<Arc<HitableHandle>>),
+    Interior {
+        left: Box<BvhNode>,
+        right: Box<BvhNode>,
+        /// The axis the children were split along, `left` is on the lower side.
+        axis: u32,
+    },
+}
+
 pub struct BvhNode {
     pub bbox: Aabb,
-    pub left: Arc<HitableHandle>,
-    pub right: Arc<HitableHandle>,
+    pub children: BvhChildren,
+}
+
+/// A BVH flattened into one array in depth-first order: the left child of an
+/// interior node comes right after it and leaves point into `primitives`.
+/// Primitives without a bounding box, like infinite planes, are kept in a list
+/// that is tested next to the tree.
+pub struct LinearBvh {
+    nodes: Vec<LinearNode>,
+    primitives: Vec<Arc<HitableHandle>>,
+    unbounded: Vec<Arc<HitableHandle>>,
+}
+
+struct LinearNode {
+    bbox: Aabb,
+    /// First primitive of a leaf, or the index of the right child
+    offset: u32,
+    /// Number of primitives, zero for interior nodes
+    count: u16,
+    /// Split axis of interior nodes
+    axis: u8,
+}
+
+struct BuildPrimitive {
+    bbox: Aabb,
+    centroid: Vec3,
+    handle: Arc<HitableHandle>,
+}
+
+#[derive(Copy, Clone)]
+struct Bin {
+    count: usize,
+    bbox: Option<Aabb>,
 }
 
 impl BvhNode {
+    /// # Panics
+    ///
+    /// If `elements` is empty or one of them has no bounding box between `t0`
+    /// and `t1`. `LinearBvh` takes those too.
     pub fn new(elements: &mut [Arc<HitableHandle>], t0: f32, t1: f32) -> Self {
-        let left: Arc<HitableHandle>;
-        let right: Arc<HitableHandle>;
-        let axis = local_rng().gen_range(0..=2);
-        elements.sort_by(|a, b| sort_bbox_by_axis(a, b, axis));
-        let len = elements.len();
+        BvhNode::with_options(elements, t0, t1, &BvhOptions::default())
+    }
+
+    /// Builds the tree with the surface area heuristic over binned centroids.
+    ///
+    /// # Panics
+    ///
+    /// Like `new`, and if the options can't make a valid tree.
+    pub fn with_options(
+        elements: &mut [Arc<HitableHandle>],
+        t0: f32,
+        t1: f32,
+        options: &BvhOptions,
+    ) -> Self {
+        assert!(!elements.is_empty(), "can't build a BVH without primitives");
+        assert!(
+            options.bins >= 2,
+            "the BVH needs at least two bins to split"
+        );
+        assert!(
+            options.max_leaf_size <= u16::MAX as usize,
+            "the BVH leaves are limited to {} primitives",
+            u16::MAX
+        );
+
+        let primitives = elements
+            .iter()
+            .map(|handle| {
+                let bbox = handle
+                    .bounding_box(t0, t1)
+                    .expect("unbounded primitives can't go in a BVH");
+                BuildPrimitive {
+                    bbox,
+                    centroid: bbox.centroid(),
+                    handle: handle.clone(),
+                }
+            })
+            .collect();
+
+        BvhNode::build(primitives, options, 0)
+    }
+
+    fn build(mut primitives: Vec<BuildPrimitive>, options: &BvhOptions, depth: usize) -> Self {
+        let bbox = merge_all(primitives.iter().map(|p| p.bbox));
+        let len = primitives.len();
         if len == 1 {
-            left = elements[0].clone();
-            right = elements[0].clone();
-        } else if len == 2 {
-            left = elements[0].clone();
-            right = elements[1].clone();
+            return BvhNode::leaf(bbox, primitives);
+        }
+
+        let centroids = merge_all(primitives.iter().map(|p| Aabb {
+            min: p.centroid,
+            max: p.centroid,
+        }));
+
+        let split = if depth < MAX_SAH_DEPTH {
+            find_split(&primitives, bbox, centroids, options)
         } else {
-            let (left_elems, right_elems) = elements.split_at_mut(len / 2);
-            left = Arc::new(Box::new(BvhNode::new(left_elems, t0, t1)));
-            right = Arc::new(Box::new(BvhNode::new(right_elems, t0, t1)));
+            None
+        };
+
+        let (axis, left, right) = match split {
+            Some((axis, split, cost)) => {
+                if cost >= len as f32 && len <= options.max_leaf_size {
+                    return BvhNode::leaf(bbox, primitives);
+                }
+                let (left, right) = primitives
+                    .into_iter()
+                    .partition(|p| bin_index(p.centroid, centroids, axis, options.bins) < split);
+                (axis, left, right)
+            }
+            None => {
+                // Every centroid is in the same spot or the tree is too deep
+                if len <= options.max_leaf_size {
+                    return BvhNode::leaf(bbox, primitives);
+                }
+                let axis = largest_axis(centroids);
+                primitives.sort_by(|a, b| a.centroid[axis].total_cmp(&b.centroid[axis]));
+                let right = primitives.split_off(len / 2);
+                (axis, primitives, right)
+            }
+        };
+
+        BvhNode {
+            bbox,
+            children: BvhChildren::Interior {
+                left: Box::new(BvhNode::build(left, options, depth + 1)),
+                right: Box::new(BvhNode::build(right, options, depth + 1)),
+                axis,
+            },
+        }
+    }
+
+    fn leaf(bbox: Aabb, primitives: Vec<BuildPrimitive>) -> Self {
+        BvhNode {
+            bbox,
+            children: BvhChildren::Leaf(primitives.into_iter().map(|p| p.handle).collect()),
+        }
+    }
+}
+
+/// Cheapest split as `(axis, first bin of the right side, cost)`, the cost is
+/// relative to intersecting one primitive.
+fn find_split(
+    primitives: &[BuildPrimitive],
+    bbox: Aabb,
+    centroids: Aabb,
+    options: &BvhOptions,
+) -> Option<(u32, usize, f32)> {
+    let area = bbox.surface_area();
+    let mut best: Option<(u32, usize, f32)> = None;
+
+    for axis in 0..3 {
+        if centroids.max[axis] <= centroids.min[axis] {
+            continue;
+        }
+
+        let mut bins = vec![
+            Bin {
+                count: 0,
+                bbox: None,
+            };
+            options.bins
+        ];
+        for p in primitives {
+            let bin = &mut bins[bin_index(p.centroid, centroids, axis, options.bins)];
+            bin.count += 1;
+            bin.bbox = Some(bin.bbox.map_or(p.bbox, |b| Aabb::merge(b, p.bbox)));
         }
-        let box_left = left.bounding_box(0.0, 0.0).unwrap();
-        let box_right = right.bounding_box(0.0, 0.0).unwrap();
-        let bbox = Aabb::merge(box_left, box_right);
 
-        BvhNode { left, right, bbox }
+        // Area-weighted counts of everything right of each split, swept from the right
+        let mut right_costs = vec![0.0; options.bins];
+        let mut right = Bin {
+            count: 0,
+            bbox: None,
+        };
+        for split in (1..options.bins).rev() {
+            right = merge_bins(right, bins[split]);
+            right_costs[split] = bin_cost(right);
+        }
+
+        let mut left = Bin {
+            count: 0,
+            bbox: None,
+        };
+        for split in 1..options.bins {
+            left = merge_bins(left, bins[split - 1]);
+            let cost = TRAVERSAL_COST + (bin_cost(left) + right_costs[split]) / area;
+            if left.count > 0
+                && left.count < primitives.len()
+                && best.is_none_or(|(_, _, best_cost)| cost < best_cost)
+            {
+                best = Some((axis, split, cost));
+            }
+        }
     }
+
+    best
 }
 
-fn sort_bbox_by_axis(left: &HitableHandle, right: &HitableHandle, axis: u32) -> Ordering {
-    let box_left = left.bounding_box(0.0, 0.0).unwrap();
-    let box_right = right.bounding_box(0.0, 0.0).unwrap();
+fn bin_index(centroid: Vec3, centroids: Aabb, axis: u32, bins: usize) -> usize {
+    let extent = centroids.max[axis] - centroids.min[axis];
+    let offset = (centroid[axis] - centroids.min[axis]) / extent;
+    ((offset * bins as f32) as usize).min(bins - 1)
+}
 
-    box_left.min[axis]
-        .partial_cmp(&box_right.min[axis])
-        .unwrap()
+fn largest_axis(bbox: Aabb) -> u32 {
+    let d = bbox.max - bbox.min;
+    if d.x >= d.y && d.x >= d.z {
+        0
+    } else if d.y >= d.z {
+        1
+    } else {
+        2
+    }
+}
+
+fn merge_bins(a: Bin, b: Bin) -> Bin {
+    let bbox = match (a.bbox, b.bbox) {
+        (Some(a), Some(b)) => Some(Aabb::merge(a, b)),
+        (a, b) => a.or(b),
+    };
+
+    Bin {
+        count: a.count + b.count,
+        bbox,
+    }
+}
+
+fn bin_cost(bin: Bin) -> f32 {
+    bin.bbox
+        .map_or(0.0, |b| bin.count as f32 * b.surface_area())
+}
+
+fn merge_all<I: Iterator<Item = Aabb>>(mut boxes: I) -> Aabb {
+    let first = boxes.next().unwrap();
+    boxes.fold(first, Aabb::merge)
+}
+
+impl LinearBvh {
+    /// Any primitives work, including unbounded ones or none at all.
+    pub fn new(elements: &mut [Arc<HitableHandle>], t0: f32, t1: f32) -> Self {
+        LinearBvh::with_options(elements, t0, t1, &BvhOptions::default())
+    }
+
+    pub fn with_options(
+        elements: &mut [Arc<HitableHandle>],
+        t0: f32,
+        t1: f32,
+        options: &BvhOptions,
+    ) -> Self {
+        let (mut bounded, unbounded): (Vec<_>, Vec<_>) = elements
+            .iter()
+            .cloned()
+            .partition(|element| element.bounding_box(t0, t1).is_some());
+
+        let mut bvh = if bounded.is_empty() {
+            LinearBvh {
+                nodes: Vec::new(),
+                primitives: Vec::new(),
+                unbounded: Vec::new(),
+            }
+        } else {
+            LinearBvh::from(BvhNode::with_options(&mut bounded[..], t0, t1, options))
+        };
+        bvh.unbounded = unbounded;
+        bvh
+    }
+
+    fn flatten(&mut self, node: BvhNode) {
+        let index = self.nodes.len();
+        self.nodes.push(LinearNode {
+            bbox: node.bbox,
+            offset: 0,
+            count: 0,
+            axis: 0,
+        });
+
+        match node.children {
+            BvhChildren::Leaf(primitives) => {
+                self.nodes[index].offset = self.primitives.len() as u32;
+                self.nodes[index].count = primitives.len() as u16;
+                self.primitives.extend(primitives);
+            }
+            BvhChildren::Interior { left, right, axis } => {
+                self.nodes[index].axis = axis as u8;
+                self.flatten(*left);
+                self.nodes[index].offset = self.nodes.len() as u32;
+                self.flatten(*right);
+            }
+        }
+    }
+
+    fn hit_nodes(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let inv_direction = Vec3::one() / ray.direction;
+        let direction_is_negative = [
+            inv_direction.x < 0.0,
+            inv_direction.y < 0.0,
+            inv_direction.z < 0.0,
+        ];
+
+        let mut stack = [0u32; STACK_SIZE];
+        let mut stack_len = 0;
+        let mut index = 0;
+        let mut closest = t_max;
+        let mut result = None;
+
+        loop {
+            let node = &self.nodes[index];
+            if node.bbox.hit_inv(ray.origin, inv_direction, t_min, closest) {
+                if node.count > 0 {
+                    let first = node.offset as usize;
+                    for primitive in &self.primitives[first..first + node.count as usize] {
+                        if let Some(hit) = primitive.hit(ray, t_min, closest) {
+                            closest = hit.t;
+                            result = Some(hit);
+                        }
+                    }
+                } else {
+                    // Visit the child on the near side first and come back for the other one
+                    let (near, far) = if direction_is_negative[node.axis as usize] {
+                        (node.offset as usize, index + 1)
+                    } else {
+                        (index + 1, node.offset as usize)
+                    };
+                    stack[stack_len] = far as u32;
+                    stack_len += 1;
+                    index = near;
+                    continue;
+                }
+            }
+
+            if stack_len == 0 {
+                break;
+            }
+            stack_len -= 1;
+            index = stack[stack_len] as usize;
+        }
+
+        result
+    }
+}
+
+impl From<BvhNode> for LinearBvh {
+    fn from(root: BvhNode) -> Self {
+        let mut bvh = LinearBvh {
+            nodes: Vec::new(),
+            primitives: Vec::new(),
+            unbounded: Vec::new(),
+        };
+        bvh.flatten(root);
+        bvh
+    }
+}
+
+impl Hitable for LinearBvh {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let mut result = self.hit_nodes(ray, t_min, t_max);
+        let mut closest = result.as_ref().map_or(t_max, |hit| hit.t);
+        for primitive in &self.unbounded {
+            if let Some(hit) = primitive.hit(ray, t_min, closest) {
+                closest = hit.t;
+                result = Some(hit);
+            }
+        }
+        result
+    }
+
+    fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<Aabb> {
+        if !self.unbounded.is_empty() {
+            return None;
+        }
+        self.nodes.first().map(|node| node.bbox)
+    }
 }
 
 impl Hitable for BvhNode {
@@ -53,23 +428,29 @@ impl Hitable for BvhNode {
             return None;
         }
 
-        let left_hit = self.left.hit(ray, t_min, t_max);
-        let right_hit = self.right.hit(ray, t_min, t_max);
-
-        if let Some(left_hit) = left_hit {
-            if let Some(right_hit) = right_hit {
-                if left_hit.t < right_hit.t {
-                    Some(left_hit)
-                } else {
-                    Some(right_hit)
+        match &self.children {
+            BvhChildren::Leaf(primitives) => {
+                let mut closest = t_max;
+                let mut result = None;
+                for primitive in primitives {
+                    if let Some(hit) = primitive.hit(ray, t_min, closest) {
+                        closest = hit.t;
+                        result = Some(hit);
+                    }
                 }
-            } else {
-                Some(left_hit)
+                result
+            }
+            BvhChildren::Interior { left, right, axis } => {
+                let (near, far) = if ray.direction[*axis] < 0.0 {
+                    (right, left)
+                } else {
+                    (left, right)
+                };
+
+                let near_hit = near.hit(ray, t_min, t_max);
+                let closest = near_hit.as_ref().map_or(t_max, |hit| hit.t);
+                far.hit(ray, t_min, closest).or(near_hit)
             }
-        } else if let Some(right_hit) = right_hit {
-            Some(right_hit)
-        } else {
-            None
         }
     }
     fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<Aabb> {
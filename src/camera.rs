@@ -1,8 +1,8 @@
 use crate::ray::Ray;
 use crate::vec3::Vec3;
 
-use crate::util::local_rng;
-use rand::Rng;
+use crate::sampler::Sampler;
+use crate::util::concentric_disk;
 
 #[derive(Debug, Clone)]
 pub struct Camera {
@@ -14,6 +14,9 @@ pub struct Camera {
     pub u: Vec3,
     pub v: Vec3,
     pub w: Vec3,
+    /// The shutter is open from `time0` to `time1`, rays get a uniform time in between
+    pub time0: f32,
+    pub time1: f32,
 }
 
 impl Camera {
@@ -46,29 +49,33 @@ impl Camera {
             v,
             w,
             lens_radius,
+            time0: 0.0,
+            time1: 0.0,
         }
     }
 
-    pub fn get_ray(&self, s: f32, t: f32) -> Ray {
-        let rd = self.lens_radius * random_in_unit_disk();
+    pub fn with_shutter(mut self, open: f32, close: f32) -> Self {
+        self.time0 = open;
+        self.time1 = close;
+        self
+    }
+
+    /// The ray through `(s, t)` on the image plane, from a point on the lens at
+    /// a time during the shutter interval picked by `sampler`.
+    pub fn get_ray(&self, s: f32, t: f32, sampler: &mut dyn Sampler) -> Ray {
+        let rd = self.lens_radius * concentric_disk(sampler.get_2d());
         let offset = self.u * rd.x + self.v * rd.y;
+        let time = if self.time1 > self.time0 {
+            self.time0 + sampler.get_1d() * (self.time1 - self.time0)
+        } else {
+            self.time0
+        };
         Ray {
             origin: self.origin + offset,
             direction: self.lower_left + s * self.horizontal + t * self.vertical
                 - self.origin
                 - offset,
+            time,
         }
     }
 }
-
-pub fn random_in_unit_disk() -> Vec3 {
-    let mut rng = local_rng();
-    let mut p: Vec3;
-    loop {
-        p = Vec3::new(rng.gen_range(-1.0..=1.0), rng.gen_range(-1.0..=1.0), 0.0);
-        if Vec3::dot(p, p) < 1.0 {
-            break;
-        }
-    }
-    p
-}
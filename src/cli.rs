@@ -0,0 +1,121 @@
+use pathtracer_rs::output::{ExrPixelType, SaveOptions};
+use pathtracer_rs::scene::Overrides;
+
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+pub const USAGE: &str = "\
+Usage: pathtracer-rs [OPTIONS] [SCENE]
+
+Renders SCENE, a JSON scene description, or the built-in random spheres scene.
+
+Options:
+    --headless            Render without opening a window and exit when done
+    --width <PIXELS>      Override the image width
+    --height <PIXELS>     Override the image height
+    --spp <SAMPLES>       Override the number of samples per pixel
+    --block-size <PIXELS> Override the size of the square render blocks
+    --threads <COUNT>     Number of render threads, all cores by default
+    --seed <SEED>         Seed of the random numbers, renders with the same seed
+                          are identical
+    --sampler <NAME>      independent, stratified, halton or sobol (default)
+    --adaptive <ERROR>    After the samples per pixel, keep sampling the blocks
+                          whose pixels have a larger standard error, e.g. 0.01
+    --max-spp <SAMPLES>   Samples per pixel at which adaptive sampling stops,
+                          8 times the samples per pixel by default
+    --spp-image <PATH>    Also save the number of samples of every pixel
+    -o, --output <PATH>   Override the output image path, .exr, .hdr and .pfm
+                          files keep the linear radiance
+    --exposure <STOPS>    Brighten or darken the image before tone mapping
+    --white-balance <KELVIN>
+                          Colour temperature of the light that should look white
+    --tone-map <NAME>     clamp (default), reinhard, reinhard_extended, aces or agx
+    --white-point <LUMINANCE>
+                          Luminance that reinhard_extended maps to white
+    --exr-float           Write 32-bit instead of 16-bit floats to .exr files
+    -h, --help            Print this message";
+
+#[derive(Debug, Default)]
+pub struct Options {
+    pub scene: Option<PathBuf>,
+    pub headless: bool,
+    pub threads: Option<usize>,
+    pub overrides: Overrides,
+    pub save: SaveOptions,
+    pub spp_image: Option<PathBuf>,
+    pub help: bool,
+}
+
+#[derive(Debug)]
+pub struct CliError(String);
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for CliError {}
+
+impl Options {
+    pub fn from_env() -> Result<Options, CliError> {
+        Options::parse(std::env::args().skip(1))
+    }
+
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Options, CliError> {
+        let mut options = Options::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| CliError(format!("missing value for `{}`", arg)))
+            };
+
+            match arg.as_str() {
+                "--headless" => options.headless = true,
+                "--width" => options.overrides.width = Some(parse_value(&arg, value()?)?),
+                "--height" => options.overrides.height = Some(parse_value(&arg, value()?)?),
+                "--spp" => options.overrides.samples = Some(parse_value(&arg, value()?)?),
+                "--block-size" => options.overrides.block_size = Some(parse_value(&arg, value()?)?),
+                "--threads" => options.threads = Some(parse_value(&arg, value()?)?),
+                "--seed" => options.overrides.seed = Some(parse_value(&arg, value()?)?),
+                "--sampler" => options.overrides.sampler = Some(parse_value(&arg, value()?)?),
+                "--adaptive" => {
+                    options.overrides.adaptive_threshold = Some(parse_value(&arg, value()?)?)
+                }
+                "--max-spp" => options.overrides.max_samples = Some(parse_value(&arg, value()?)?),
+                "--spp-image" => options.spp_image = Some(PathBuf::from(value()?)),
+                "-o" | "--output" => options.overrides.output = Some(PathBuf::from(value()?)),
+                "--exposure" => options.overrides.exposure = Some(parse_value(&arg, value()?)?),
+                "--white-balance" => {
+                    options.overrides.white_balance = Some(parse_value(&arg, value()?)?)
+                }
+                "--tone-map" => options.overrides.tone_map = Some(parse_value(&arg, value()?)?),
+                "--white-point" => {
+                    options.overrides.white_point = Some(parse_value(&arg, value()?)?)
+                }
+                "--exr-float" => options.save.exr_pixel_type = ExrPixelType::Float,
+                "-h" | "--help" => options.help = true,
+                _ if arg.starts_with('-') => {
+                    return Err(CliError(format!("unknown option `{}`", arg)))
+                }
+                _ => {
+                    if options.scene.is_some() {
+                        return Err(CliError(format!("unexpected argument `{}`", arg)));
+                    }
+                    options.scene = Some(PathBuf::from(arg));
+                }
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+fn parse_value<T: FromStr>(arg: &str, value: String) -> Result<T, CliError> {
+    value
+        .parse()
+        .map_err(|_| CliError(format!("invalid value `{}` for `{}`", value, arg)))
+}
@@ -0,0 +1,114 @@
+/// Piecewise-constant density on `[0, 1)` proportional to a list of
+/// non-negative values. Falls back to a uniform density if they are all zero.
+pub struct Distribution1D {
+    func: Vec<f32>,
+    /// `cdf[i]` is the probability of sampling one of the first `i` buckets
+    cdf: Vec<f32>,
+    /// Integral of `func` over `[0, 1)`
+    integral: f32,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f32>) -> Self {
+        assert!(!func.is_empty(), "distribution must not be empty");
+
+        let n = func.len();
+        // Summed in f64 so that long lists of small values don't lose precision
+        let mut sums = Vec::with_capacity(n + 1);
+        let mut sum = 0.0f64;
+        sums.push(sum);
+        for &value in &func {
+            assert!(value >= 0.0, "distribution values must not be negative");
+            sum += value as f64 / n as f64;
+            sums.push(sum);
+        }
+
+        let cdf = if sum > 0.0 {
+            sums.iter().map(|&s| (s / sum) as f32).collect()
+        } else {
+            (0..=n).map(|i| i as f32 / n as f32).collect()
+        };
+
+        Distribution1D {
+            func,
+            cdf,
+            integral: sum as f32,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    /// Maps a uniform `u` in `[0, 1)` to a point of the distribution. Returns the
+    /// point, its density and the bucket it falls into.
+    pub fn sample(&self, u: f32) -> (f32, f32, usize) {
+        let n = self.count();
+        // The last bucket starting at or before `u`, which skips empty buckets
+        let offset = (self.cdf.partition_point(|&c| c <= u) - 1).min(n - 1);
+
+        let start = self.cdf[offset];
+        let width = self.cdf[offset + 1] - start;
+        let du = if width > 0.0 {
+            (u - start) / width
+        } else {
+            0.0
+        };
+
+        let x = ((offset as f32 + du) / n as f32).min(1.0 - f32::EPSILON);
+        (x, self.pdf(offset), offset)
+    }
+
+    /// Density of the points in bucket `index`.
+    pub fn pdf(&self, index: usize) -> f32 {
+        if self.integral > 0.0 {
+            self.func[index] / self.integral
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Piecewise-constant density on `[0, 1)^2` over a grid of non-negative values,
+/// sampled by picking a row from the marginal density and then a column in it.
+pub struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// `func` holds `height` rows of `width` values.
+    pub fn new(func: &[f32], width: usize, height: usize) -> Self {
+        assert_eq!(func.len(), width * height);
+
+        let rows: Vec<_> = func
+            .chunks_exact(width)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(rows.iter().map(|row| row.integral()).collect());
+
+        Distribution2D { rows, marginal }
+    }
+
+    pub fn integral(&self) -> f32 {
+        self.marginal.integral()
+    }
+
+    /// Maps two uniform values to a point `(x, y)` and its density.
+    pub fn sample(&self, u: f32, v: f32) -> ((f32, f32), f32) {
+        let (y, row_pdf, row) = self.marginal.sample(v);
+        let (x, column_pdf, _) = self.rows[row].sample(u);
+        ((x, y), row_pdf * column_pdf)
+    }
+
+    /// Density of `sample` picking the point `(x, y)`.
+    pub fn pdf(&self, x: f32, y: f32) -> f32 {
+        let row = ((y * self.rows.len() as f32) as usize).min(self.rows.len() - 1);
+        let column = ((x * self.rows[row].count() as f32) as usize).min(self.rows[row].count() - 1);
+        self.marginal.pdf(row) * self.rows[row].pdf(column)
+    }
+}
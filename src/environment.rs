@@ -0,0 +1,298 @@
+use crate::distribution::Distribution2D;
+use crate::light::{Light, LightSample};
+use crate::transform::{Mat3, Quat};
+use crate::vec3::Vec3;
+
+use image::codecs::hdr::HdrDecoder;
+use image::ImageError;
+use std::f32::consts::PI;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+
+/// Light arriving from infinitely far away, seen by the rays that leave the scene.
+pub trait Environment {
+    /// Radiance arriving along the unit vector `-direction`, i.e. what a ray
+    /// going in `direction` sees.
+    fn radiance(&self, direction: Vec3) -> Vec3;
+
+    /// The environment as a light for next event estimation, if it can be
+    /// sampled directly. Its samples are infinitely far away.
+    fn as_light(&self) -> Option<&dyn Light> {
+        None
+    }
+}
+
+pub type EnvironmentHandle = Box<dyn Environment + Send + Sync>;
+
+pub struct ConstantEnvironment {
+    pub color: Vec3,
+}
+
+impl Environment for ConstantEnvironment {
+    fn radiance(&self, _direction: Vec3) -> Vec3 {
+        self.color
+    }
+}
+
+/// Blends from `bottom` straight down to `top` straight up.
+pub struct GradientEnvironment {
+    pub bottom: Vec3,
+    pub top: Vec3,
+}
+
+impl Default for GradientEnvironment {
+    /// A white to blue sky.
+    fn default() -> Self {
+        GradientEnvironment {
+            bottom: Vec3::one(),
+            top: Vec3::new(0.5, 0.7, 1.0),
+        }
+    }
+}
+
+impl Environment for GradientEnvironment {
+    fn radiance(&self, direction: Vec3) -> Vec3 {
+        let t = 0.5 * (direction.y + 1.0);
+        (1.0 - t) * self.bottom + t * self.top
+    }
+}
+
+#[derive(Debug)]
+pub enum EnvironmentError {
+    Io(io::Error),
+    Image(ImageError),
+    Exr(exr::error::Error),
+    UnknownFormat(PathBuf),
+}
+
+impl fmt::Display for EnvironmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvironmentError::Io(e) => write!(f, "{}", e),
+            EnvironmentError::Image(e) => write!(f, "{}", e),
+            EnvironmentError::Exr(e) => write!(f, "{}", e),
+            EnvironmentError::UnknownFormat(path) => {
+                write!(f, "{} is neither a .hdr nor an .exr file", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for EnvironmentError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EnvironmentError::Io(e) => Some(e),
+            EnvironmentError::Image(e) => Some(e),
+            EnvironmentError::Exr(e) => Some(e),
+            EnvironmentError::UnknownFormat(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for EnvironmentError {
+    fn from(e: io::Error) -> Self {
+        EnvironmentError::Io(e)
+    }
+}
+
+impl From<ImageError> for EnvironmentError {
+    fn from(e: ImageError) -> Self {
+        EnvironmentError::Image(e)
+    }
+}
+
+impl From<exr::error::Error> for EnvironmentError {
+    fn from(e: exr::error::Error) -> Self {
+        EnvironmentError::Exr(e)
+    }
+}
+
+/// A latitude-longitude panorama with +y at the top row and -z in the middle
+/// column, bilinearly filtered. Directions are importance sampled by the
+/// luminance of the pixels, so small bright lights like the sun don't have to
+/// be found by chance.
+pub struct EnvironmentMap {
+    width: u32,
+    height: u32,
+    /// Linear RGB, rows from top to bottom
+    pixels: Vec<Vec3>,
+    /// World to map space
+    rotation: Mat3,
+    intensity: f32,
+    /// Over the pixels, weighted by the solid angle they cover
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    /// Reads a Radiance `.hdr` or an OpenEXR `.exr` file.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, EnvironmentError> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+
+        match extension.as_deref() {
+            Some("hdr") => {
+                let decoder = HdrDecoder::new(BufReader::new(File::open(path)?))?;
+                let metadata = decoder.metadata();
+                let pixels = decoder
+                    .read_image_hdr()?
+                    .iter()
+                    .map(|p| Vec3::new(p[0], p[1], p[2]))
+                    .collect();
+                Ok(EnvironmentMap::from_pixels(
+                    metadata.width,
+                    metadata.height,
+                    pixels,
+                ))
+            }
+            Some("exr") => {
+                let image = exr::prelude::read_first_rgba_layer_from_file(
+                    path,
+                    |resolution, _| {
+                        (
+                            resolution.width(),
+                            vec![Vec3::zero(); resolution.width() * resolution.height()],
+                        )
+                    },
+                    |(width, pixels), position, (r, g, b, _): (f32, f32, f32, f32)| {
+                        pixels[position.y() * *width + position.x()] = Vec3::new(r, g, b);
+                    },
+                )?;
+                let size = image.layer_data.size;
+                let (_, pixels) = image.layer_data.channel_data.pixels;
+                Ok(EnvironmentMap::from_pixels(
+                    size.width() as u32,
+                    size.height() as u32,
+                    pixels,
+                ))
+            }
+            _ => Err(EnvironmentError::UnknownFormat(path.to_owned())),
+        }
+    }
+
+    pub fn from_pixels(width: u32, height: u32, pixels: Vec<Vec3>) -> Self {
+        assert!(width > 0 && height > 0, "environment map must not be empty");
+        assert_eq!(pixels.len(), (width * height) as usize);
+
+        // Rows near the poles are squeezed into a smaller solid angle
+        let weights: Vec<f32> = pixels
+            .iter()
+            .enumerate()
+            .map(|(idx, p)| {
+                let row = idx / width as usize;
+                let sin_theta = (PI * (row as f32 + 0.5) / height as f32).sin();
+                p.luminance().max(0.0) * sin_theta
+            })
+            .collect();
+        let distribution = Distribution2D::new(&weights, width as usize, height as usize);
+
+        EnvironmentMap {
+            width,
+            height,
+            pixels,
+            rotation: Mat3::identity(),
+            intensity: 1.0,
+            distribution,
+        }
+    }
+
+    /// Turns the map by `radians` counterclockwise around the y axis, seen from above.
+    pub fn with_rotation(mut self, radians: f32) -> Self {
+        self.rotation = Quat::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), -radians).to_mat3();
+        self
+    }
+
+    /// Scales the radiance of every pixel.
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Coordinates in `[0, 1]^2`, from the top left corner, seen along a unit
+    /// `direction`.
+    fn direction_to_uv(&self, direction: Vec3) -> (f32, f32) {
+        let d = self.rotation * direction;
+        let u = 0.5 + d.x.atan2(-d.z) / (2.0 * PI);
+        let v = d.y.clamp(-1.0, 1.0).acos() / PI;
+        (u, v)
+    }
+
+    /// The unit direction at `(u, v)` and the sine of its polar angle.
+    fn uv_to_direction(&self, u: f32, v: f32) -> (Vec3, f32) {
+        let phi = 2.0 * PI * (u - 0.5);
+        let theta = PI * v;
+        let sin_theta = theta.sin();
+        let d = Vec3::new(sin_theta * phi.sin(), theta.cos(), -sin_theta * phi.cos());
+        (self.rotation.transpose() * d, sin_theta)
+    }
+
+    /// Wraps around horizontally and clamps at the poles.
+    fn texel(&self, x: i64, y: i64) -> Vec3 {
+        let x = x.rem_euclid(self.width as i64) as usize;
+        let y = y.clamp(0, self.height as i64 - 1) as usize;
+        self.pixels[y * self.width as usize + x]
+    }
+}
+
+impl Environment for EnvironmentMap {
+    fn radiance(&self, direction: Vec3) -> Vec3 {
+        // Texel centres are at half-integer coordinates
+        let (u, v) = self.direction_to_uv(direction);
+        let x = u * self.width as f32 - 0.5;
+        let y = v * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = (1.0 - fx) * self.texel(x0, y0) + fx * self.texel(x0 + 1, y0);
+        let bottom = (1.0 - fx) * self.texel(x0, y0 + 1) + fx * self.texel(x0 + 1, y0 + 1);
+        self.intensity * ((1.0 - fy) * top + fy * bottom)
+    }
+
+    fn as_light(&self) -> Option<&dyn Light> {
+        if self.intensity > 0.0 && self.distribution.integral() > 0.0 {
+            Some(self)
+        } else {
+            None
+        }
+    }
+}
+
+impl Light for EnvironmentMap {
+    fn sample(&self, _origin: Vec3, (s, t): (f32, f32)) -> Option<LightSample> {
+        let ((u, v), uv_pdf) = self.distribution.sample(s, t);
+        let (direction, sin_theta) = self.uv_to_direction(u, v);
+        if uv_pdf == 0.0 || sin_theta == 0.0 {
+            return None;
+        }
+
+        // The map covers 2 pi by pi radians, stretched by sin(theta) away from the poles
+        Some(LightSample {
+            direction,
+            distance: f32::MAX,
+            pdf: uv_pdf / (2.0 * PI * PI * sin_theta),
+            radiance: self.radiance(direction),
+        })
+    }
+
+    fn pdf(&self, _origin: Vec3, direction: Vec3) -> f32 {
+        let (u, v) = self.direction_to_uv(direction);
+        let sin_theta = (PI * v).sin();
+        if sin_theta == 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf(u, v) / (2.0 * PI * PI * sin_theta)
+    }
+}
@@ -0,0 +1,105 @@
+use crate::tonemap::ToneMap;
+use crate::vec3::Vec3;
+
+/// Per-pixel radiance sums and sample counts, rows from top to bottom.
+///
+/// Every render pass adds to the sums, so the image converges to the average
+/// of all the samples taken so far. The sums of the squared luminances give
+/// the variance of each pixel for adaptive sampling.
+pub struct Film {
+    width: u32,
+    height: u32,
+    sums: Vec<Vec3>,
+    squares: Vec<f32>,
+    counts: Vec<u32>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Self {
+        let len = (width * height) as usize;
+        Film {
+            width,
+            height,
+            sums: vec![Vec3::zero(); len],
+            squares: vec![0.0; len],
+            counts: vec![0; len],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Adds `samples` samples to every pixel of a run starting at `offset`,
+    /// `sums` holding the radiance sum of each pixel and `squares` the sum of
+    /// the squared luminances.
+    pub fn add_samples(&mut self, offset: usize, sums: &[Vec3], squares: &[f32], samples: u32) {
+        assert_eq!(sums.len(), squares.len());
+
+        let end = offset + sums.len();
+        for (sum, new) in self.sums[offset..end].iter_mut().zip(sums) {
+            *sum += *new;
+        }
+        for (square, new) in self.squares[offset..end].iter_mut().zip(squares) {
+            *square += *new;
+        }
+        for count in &mut self.counts[offset..end] {
+            *count += samples;
+        }
+    }
+
+    pub fn sample_count(&self, idx: usize) -> u32 {
+        self.counts[idx]
+    }
+
+    /// Average radiance of the pixel, black if it has no samples yet.
+    pub fn average(&self, idx: usize) -> Vec3 {
+        match self.counts[idx] {
+            0 => Vec3::zero(),
+            count => self.sums[idx] / count as f32,
+        }
+    }
+
+    /// Standard error of the mean luminance of the pixel, multiplied by `scale`
+    /// and put through a square root, which is close to the sRGB curve, so
+    /// that dark pixels don't need as many samples. Infinite until the pixel
+    /// has two samples.
+    pub fn error(&self, idx: usize, scale: f32) -> f32 {
+        let count = self.counts[idx];
+        if count < 2 {
+            return f32::INFINITY;
+        }
+
+        let n = count as f32;
+        let mean = self.sums[idx].luminance() / n;
+        let variance = ((self.squares[idx] / n - mean * mean) * n / (n - 1.0)).max(0.0);
+        // d sqrt(x) = dx / (2 sqrt(x))
+        scale * (variance / n).sqrt() / (2.0 * (scale * mean).max(1e-4).sqrt())
+    }
+
+    pub fn clear(&mut self) {
+        for sum in &mut self.sums {
+            *sum = Vec3::zero();
+        }
+        for square in &mut self.squares {
+            *square = 0.0;
+        }
+        for count in &mut self.counts {
+            *count = 0;
+        }
+    }
+
+    /// Writes the tone mapped running average as sRGB encoded RGBA8.
+    pub fn to_rgba8(&self, frame: &mut [u8], tone_map: &ToneMap) {
+        assert_eq!(frame.len(), self.sums.len() * 4);
+
+        for (idx, rgba) in frame.chunks_exact_mut(4).enumerate() {
+            let [r, g, b] = tone_map.to_srgb8(self.average(idx));
+            rgba.copy_from_slice(&[r, g, b, 0xff]);
+        }
+    }
+}
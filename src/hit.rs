@@ -1,8 +1,11 @@
 use crate::aabb::Aabb;
 use crate::iter_util::IteratorExt;
+use crate::light::{Light, LightId};
 use crate::material::MaterialHandle;
 use crate::ray::Ray;
+use crate::transform::Onb;
 use crate::vec3::Vec3;
+use std::f32::consts::PI;
 use std::sync::Arc;
 
 #[derive(Clone)]
@@ -10,12 +13,22 @@ pub struct HitRecord {
     pub t: f32,
     pub point: Vec3,
     pub normal: Vec3,
+    /// Surface coordinates for textures
+    pub u: f32,
+    pub v: f32,
     pub material: Arc<MaterialHandle>,
+    /// The primitive that was hit, if it can be sampled as a light
+    pub light: Option<LightId>,
 }
 
 pub trait Hitable {
     fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord>;
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<Aabb>;
+
+    /// The primitive as a light, if it is emissive and can be sampled directly.
+    fn as_light(&self) -> Option<&dyn Light> {
+        None
+    }
 }
 
 pub type HitableHandle = Box<dyn Hitable + Send + Sync>;
@@ -56,36 +69,56 @@ pub struct Sphere {
     pub material: Arc<MaterialHandle>,
 }
 
-impl Hitable for Sphere {
-    #[allow(clippy::suspicious_operation_groupings)]
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let oc = ray.origin - self.center;
-        let a = Vec3::dot(ray.direction, ray.direction);
-        let b = Vec3::dot(oc, ray.direction);
-        let c = Vec3::dot(oc, oc) - self.radius * self.radius;
-        let discriminant = b * b - a * c;
-        if discriminant > 0.0 {
-            let temp = (-b - discriminant.sqrt()) / a;
+#[allow(clippy::suspicious_operation_groupings)]
+fn hit_sphere(
+    center: Vec3,
+    radius: f32,
+    material: &Arc<MaterialHandle>,
+    ray: &Ray,
+    t_min: f32,
+    t_max: f32,
+) -> Option<HitRecord> {
+    let oc = ray.origin - center;
+    let a = Vec3::dot(ray.direction, ray.direction);
+    let b = Vec3::dot(oc, ray.direction);
+    let c = Vec3::dot(oc, oc) - radius * radius;
+    let discriminant = b * b - a * c;
+    if discriminant > 0.0 {
+        let root = discriminant.sqrt();
+        for &temp in &[(-b - root) / a, (-b + root) / a] {
             if temp > t_min && temp < t_max {
+                let point = ray.point_at(temp);
+                let normal = (point - center) / radius;
+                let (u, v) = sphere_uv(normal);
                 return Some(HitRecord {
                     t: temp,
-                    point: ray.point_at(temp),
-                    normal: (ray.point_at(temp) - self.center) / self.radius,
-                    material: self.material.clone(),
-                });
-            }
-            let temp = (-b + discriminant.sqrt()) / a;
-            if temp > t_min && temp < t_max {
-                return Some(HitRecord {
-                    t: temp,
-                    point: ray.point_at(temp),
-                    normal: (ray.point_at(temp) - self.center) / self.radius,
-                    material: self.material.clone(),
+                    point,
+                    normal,
+                    u,
+                    v,
+                    material: material.clone(),
+                    light: None,
                 });
             }
         }
+    }
 
-        None
+    None
+}
+
+/// Longitude and latitude of a point on the unit sphere, `v = 0` at the bottom
+/// and `u = 0` towards -x.
+fn sphere_uv(p: Vec3) -> (f32, f32) {
+    let theta = (-p.y).clamp(-1.0, 1.0).acos();
+    let phi = (-p.z).atan2(p.x) + PI;
+    (phi / (2.0 * PI), theta / PI)
+}
+
+impl Hitable for Sphere {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let mut hit = hit_sphere(self.center, self.radius, &self.material, ray, t_min, t_max)?;
+        hit.light = self.as_light().map(LightId::of);
+        Some(hit)
     }
 
     fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<Aabb> {
@@ -94,4 +127,435 @@ impl Hitable for Sphere {
             max: self.center + Vec3::new(self.radius, self.radius, self.radius),
         })
     }
+
+    fn as_light(&self) -> Option<&dyn Light> {
+        if self.material.is_emissive() {
+            Some(self)
+        } else {
+            None
+        }
+    }
+}
+
+/// Sphere moving in a straight line from `center0` at `time0` to `center1` at
+/// `time1`, and beyond. It isn't sampled as a light.
+#[derive(Clone)]
+pub struct MovingSphere {
+    pub center0: Vec3,
+    pub center1: Vec3,
+    pub time0: f32,
+    pub time1: f32,
+    pub radius: f32,
+    pub material: Arc<MaterialHandle>,
+}
+
+impl MovingSphere {
+    pub fn center(&self, time: f32) -> Vec3 {
+        if self.time1 == self.time0 {
+            return self.center0;
+        }
+        let s = (time - self.time0) / (self.time1 - self.time0);
+        self.center0 + s * (self.center1 - self.center0)
+    }
+}
+
+impl Hitable for MovingSphere {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let center = self.center(ray.time);
+        hit_sphere(center, self.radius, &self.material, ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self, t0: f32, t1: f32) -> Option<Aabb> {
+        let r = Vec3::new(self.radius, self.radius, self.radius);
+        let (c0, c1) = (self.center(t0), self.center(t1));
+        Some(Aabb::merge(
+            Aabb {
+                min: c0 - r,
+                max: c0 + r,
+            },
+            Aabb {
+                min: c1 - r,
+                max: c1 + r,
+            },
+        ))
+    }
+}
+
+#[derive(Clone)]
+pub struct Triangle {
+    pub vertices: [Vec3; 3],
+    pub normals: Option<[Vec3; 3]>,
+    pub material: Arc<MaterialHandle>,
+}
+
+impl Hitable for Triangle {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let [p0, p1, p2] = self.vertices;
+        let (t, bary) = intersect_triangle(ray, p0, p1, p2, t_min, t_max)?;
+        let normal = match self.normals {
+            Some([n0, n1, n2]) => (bary[0] * n0 + bary[1] * n1 + bary[2] * n2).unit(),
+            None => Vec3::cross(p1 - p0, p2 - p0).unit(),
+        };
+
+        Some(HitRecord {
+            t,
+            point: ray.point_at(t),
+            normal,
+            u: bary[1],
+            v: bary[2],
+            material: self.material.clone(),
+            light: self.as_light().map(LightId::of),
+        })
+    }
+
+    fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<Aabb> {
+        let [p0, p1, p2] = self.vertices;
+        Some(Aabb::from_points(&[p0, p1, p2]).padded(TRIANGLE_BBOX_PADDING))
+    }
+
+    fn as_light(&self) -> Option<&dyn Light> {
+        if self.material.is_emissive() {
+            Some(self)
+        } else {
+            None
+        }
+    }
+}
+
+/// Infinite plane through `point`, it has no bounding box so it is tested
+/// outside of the BVH.
+#[derive(Clone)]
+pub struct Plane {
+    pub point: Vec3,
+    pub normal: Vec3,
+    pub material: Arc<MaterialHandle>,
+}
+
+impl Hitable for Plane {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let denom = Vec3::dot(self.normal, ray.direction);
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+
+        let t = Vec3::dot(self.normal, self.point - ray.origin) / denom;
+        if t <= t_min || t >= t_max {
+            return None;
+        }
+
+        // Planar coordinates in world units, textures repeat or clamp them
+        let point = ray.point_at(t);
+        let onb = Onb::from_w(self.normal);
+        let offset = point - self.point;
+
+        Some(HitRecord {
+            t,
+            point,
+            normal: onb.w,
+            u: Vec3::dot(offset, onb.u),
+            v: Vec3::dot(offset, onb.v),
+            material: self.material.clone(),
+            light: None,
+        })
+    }
+
+    fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<Aabb> {
+        None
+    }
+}
+
+/// Parallelogram spanning `corner + a * u + b * v` for `a` and `b` in `[0, 1]`,
+/// facing `u x v`.
+#[derive(Clone)]
+pub struct Quad {
+    pub corner: Vec3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub material: Arc<MaterialHandle>,
+}
+
+impl Quad {
+    pub fn normal(&self) -> Vec3 {
+        Vec3::cross(self.u, self.v).unit()
+    }
+}
+
+impl Hitable for Quad {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let n = Vec3::cross(self.u, self.v);
+        let denom = Vec3::dot(n, ray.direction);
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+
+        let t = Vec3::dot(n, self.corner - ray.origin) / denom;
+        if t <= t_min || t >= t_max {
+            return None;
+        }
+
+        // Planar coordinates of the hit point along `u` and `v`
+        let point = ray.point_at(t);
+        let p = point - self.corner;
+        let w = n / Vec3::dot(n, n);
+        let a = Vec3::dot(w, Vec3::cross(p, self.v));
+        let b = Vec3::dot(w, Vec3::cross(self.u, p));
+        if !(0.0..=1.0).contains(&a) || !(0.0..=1.0).contains(&b) {
+            return None;
+        }
+
+        Some(HitRecord {
+            t,
+            point,
+            normal: n.unit(),
+            u: a,
+            v: b,
+            material: self.material.clone(),
+            light: self.as_light().map(LightId::of),
+        })
+    }
+
+    fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<Aabb> {
+        let points = [
+            self.corner,
+            self.corner + self.u,
+            self.corner + self.v,
+            self.corner + self.u + self.v,
+        ];
+        Some(Aabb::from_points(&points).padded(TRIANGLE_BBOX_PADDING))
+    }
+
+    fn as_light(&self) -> Option<&dyn Light> {
+        if self.material.is_emissive() {
+            Some(self)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MeshFace {
+    pub positions: [u32; 3],
+    pub normals: Option<[u32; 3]>,
+    pub uvs: Option<[u32; 3]>,
+    pub material: u32,
+}
+
+/// Indexed triangle mesh. Faces index into the shared vertex buffers and into `materials`.
+#[derive(Clone, Default)]
+pub struct TriangleMesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<[f32; 2]>,
+    pub faces: Vec<MeshFace>,
+    pub materials: Vec<Arc<MaterialHandle>>,
+}
+
+impl TriangleMesh {
+    /// Splits the mesh into one hitable per face, sharing the mesh buffers, so that
+    /// the faces can be put into a `BvhNode` along with other primitives.
+    pub fn triangles(mesh: &Arc<TriangleMesh>) -> Vec<Arc<HitableHandle>> {
+        (0..mesh.faces.len())
+            .map(|face| {
+                Arc::new(Box::new(MeshTriangle {
+                    mesh: mesh.clone(),
+                    face,
+                }) as HitableHandle)
+            })
+            .collect()
+    }
+
+    fn face_vertices(&self, face: &MeshFace) -> [Vec3; 3] {
+        let [i0, i1, i2] = face.positions;
+        [
+            self.positions[i0 as usize],
+            self.positions[i1 as usize],
+            self.positions[i2 as usize],
+        ]
+    }
+
+    fn hit_face(&self, face: &MeshFace, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let [p0, p1, p2] = self.face_vertices(face);
+        let (t, bary) = intersect_triangle(ray, p0, p1, p2, t_min, t_max)?;
+        let normal = match face.normals {
+            Some([i0, i1, i2]) => (bary[0] * self.normals[i0 as usize]
+                + bary[1] * self.normals[i1 as usize]
+                + bary[2] * self.normals[i2 as usize])
+                .unit(),
+            None => Vec3::cross(p1 - p0, p2 - p0).unit(),
+        };
+        let (u, v) = match face.uvs {
+            Some([i0, i1, i2]) => {
+                let [uv0, uv1, uv2] = [
+                    self.uvs[i0 as usize],
+                    self.uvs[i1 as usize],
+                    self.uvs[i2 as usize],
+                ];
+                (
+                    bary[0] * uv0[0] + bary[1] * uv1[0] + bary[2] * uv2[0],
+                    bary[0] * uv0[1] + bary[1] * uv1[1] + bary[2] * uv2[1],
+                )
+            }
+            None => (bary[1], bary[2]),
+        };
+
+        Some(HitRecord {
+            t,
+            point: ray.point_at(t),
+            normal,
+            u,
+            v,
+            material: self.materials[face.material as usize].clone(),
+            light: None,
+        })
+    }
+}
+
+impl Hitable for TriangleMesh {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let mut closest_so_far = t_max;
+        let mut res = None;
+        for face in &self.faces {
+            if let Some(hit_record) = self.hit_face(face, ray, t_min, closest_so_far) {
+                closest_so_far = hit_record.t;
+                res = Some(hit_record);
+            }
+        }
+        res
+    }
+
+    fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<Aabb> {
+        if self.faces.is_empty() {
+            return None;
+        }
+        Some(Aabb::from_points(&self.positions).padded(TRIANGLE_BBOX_PADDING))
+    }
+}
+
+/// A single face of a shared `TriangleMesh`.
+#[derive(Clone)]
+pub struct MeshTriangle {
+    pub mesh: Arc<TriangleMesh>,
+    pub face: usize,
+}
+
+impl MeshTriangle {
+    pub fn vertices(&self) -> [Vec3; 3] {
+        self.mesh.face_vertices(&self.mesh.faces[self.face])
+    }
+
+    pub fn material(&self) -> &Arc<MaterialHandle> {
+        &self.mesh.materials[self.mesh.faces[self.face].material as usize]
+    }
+}
+
+impl Hitable for MeshTriangle {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let mut hit = self
+            .mesh
+            .hit_face(&self.mesh.faces[self.face], ray, t_min, t_max)?;
+        hit.light = self.as_light().map(LightId::of);
+        Some(hit)
+    }
+
+    fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<Aabb> {
+        Some(Aabb::from_points(&self.vertices()).padded(TRIANGLE_BBOX_PADDING))
+    }
+
+    fn as_light(&self) -> Option<&dyn Light> {
+        if self.material().is_emissive() {
+            Some(self)
+        } else {
+            None
+        }
+    }
+}
+
+// Axis-aligned triangles and quads have flat boxes, which `Aabb::hit` never reports as hit.
+const TRIANGLE_BBOX_PADDING: f32 = 1e-4;
+
+/// Watertight ray/triangle intersection (Woop, Benthin, Wald 2013).
+///
+/// Returns the ray parameter and the barycentric weights of `p0`, `p1` and `p2`.
+#[allow(clippy::many_single_char_names)]
+fn intersect_triangle(
+    ray: &Ray,
+    p0: Vec3,
+    p1: Vec3,
+    p2: Vec3,
+    t_min: f32,
+    t_max: f32,
+) -> Option<(f32, [f32; 3])> {
+    let dir = ray.direction;
+
+    // Permute the axes so that z is the dominant direction component,
+    // swapping x and y to preserve the winding.
+    let kz = max_dimension(dir);
+    let mut kx = (kz + 1) % 3;
+    let mut ky = (kx + 1) % 3;
+    if dir[kz] < 0.0 {
+        std::mem::swap(&mut kx, &mut ky);
+    }
+
+    let sx = dir[kx] / dir[kz];
+    let sy = dir[ky] / dir[kz];
+    let sz = 1.0 / dir[kz];
+
+    let a = p0 - ray.origin;
+    let b = p1 - ray.origin;
+    let c = p2 - ray.origin;
+
+    let ax = a[kx] - sx * a[kz];
+    let ay = a[ky] - sy * a[kz];
+    let bx = b[kx] - sx * b[kz];
+    let by = b[ky] - sy * b[kz];
+    let cx = c[kx] - sx * c[kz];
+    let cy = c[ky] - sy * c[kz];
+
+    let mut u = cx * by - cy * bx;
+    let mut v = ax * cy - ay * cx;
+    let mut w = bx * ay - by * ax;
+
+    // Fall back to double precision on edges so that no ray slips between
+    // neighbouring triangles.
+    if u == 0.0 || v == 0.0 || w == 0.0 {
+        u = (cx as f64 * by as f64 - cy as f64 * bx as f64) as f32;
+        v = (ax as f64 * cy as f64 - ay as f64 * cx as f64) as f32;
+        w = (bx as f64 * ay as f64 - by as f64 * ax as f64) as f32;
+    }
+
+    if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+        return None;
+    }
+
+    let det = u + v + w;
+    if det == 0.0 {
+        return None;
+    }
+
+    let az = sz * a[kz];
+    let bz = sz * b[kz];
+    let cz = sz * c[kz];
+    let t = (u * az + v * bz + w * cz) / det;
+    if t <= t_min || t >= t_max {
+        return None;
+    }
+
+    Some((t, [u / det, v / det, w / det]))
+}
+
+#[inline]
+fn max_dimension(v: Vec3) -> u32 {
+    let (x, y, z) = (v.x.abs(), v.y.abs(), v.z.abs());
+    if x > y {
+        if x > z {
+            0
+        } else {
+            2
+        }
+    } else if y > z {
+        1
+    } else {
+        2
+    }
 }
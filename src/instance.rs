@@ -0,0 +1,72 @@
+use crate::aabb::Aabb;
+use crate::hit::{HitRecord, Hitable, HitableHandle};
+use crate::ray::Ray;
+use crate::transform::{AnimatedTransform, Transform};
+
+use std::sync::Arc;
+
+/// Places a shared object, usually the BVH of a mesh, in the world with an
+/// affine transform. Rays are moved into object space instead of transforming
+/// the geometry, so any number of instances can share one copy of it.
+///
+/// Emissive instances aren't sampled as lights, their emission is only found by
+/// the rays that happen to hit them.
+pub struct Instance {
+    pub object: Arc<HitableHandle>,
+    /// Object to world space
+    pub transform: Transform,
+}
+
+impl Instance {
+    pub fn new(object: Arc<HitableHandle>, transform: Transform) -> Self {
+        Instance { object, transform }
+    }
+}
+
+impl Hitable for Instance {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let local_ray = self.transform.inverse().ray(ray);
+
+        let mut hit = self.object.hit(&local_ray, t_min, t_max)?;
+        hit.point = self.transform.point(hit.point);
+        hit.normal = self.transform.normal(hit.normal).unit();
+        // Only untransformed primitives are sampled as lights
+        hit.light = None;
+        Some(hit)
+    }
+
+    fn bounding_box(&self, t0: f32, t1: f32) -> Option<Aabb> {
+        let bbox = self.object.bounding_box(t0, t1)?;
+        Some(self.transform.aabb(bbox))
+    }
+}
+
+/// An `Instance` whose transform changes over time, for motion blur.
+pub struct AnimatedInstance {
+    pub object: Arc<HitableHandle>,
+    pub transform: AnimatedTransform,
+}
+
+impl AnimatedInstance {
+    pub fn new(object: Arc<HitableHandle>, transform: AnimatedTransform) -> Self {
+        AnimatedInstance { object, transform }
+    }
+}
+
+impl Hitable for AnimatedInstance {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let transform = self.transform.at(ray.time);
+        let local_ray = transform.inverse().ray(ray);
+
+        let mut hit = self.object.hit(&local_ray, t_min, t_max)?;
+        hit.point = transform.point(hit.point);
+        hit.normal = transform.normal(hit.normal).unit();
+        hit.light = None;
+        Some(hit)
+    }
+
+    fn bounding_box(&self, t0: f32, t1: f32) -> Option<Aabb> {
+        let bbox = self.object.bounding_box(t0, t1)?;
+        Some(self.transform.aabb(bbox, t0, t1))
+    }
+}
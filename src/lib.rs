@@ -0,0 +1,62 @@
+//! A CPU path tracer.
+//!
+//! Build a world out of `Hitable`s, point a `Camera` at it and render it with a
+//! `Tracer`, or load all of that from a scene file with `scene::load_scene`.
+
+#![deny(clippy::all)]
+#![forbid(unsafe_code)]
+#![allow(clippy::many_single_char_names)]
+#![allow(clippy::identity_op)]
+
+pub mod aabb;
+pub mod bvh;
+pub mod camera;
+pub mod distribution;
+pub mod environment;
+pub mod film;
+pub mod hit;
+pub mod instance;
+mod iter_util;
+pub mod light;
+pub mod material;
+pub mod obj;
+pub mod output;
+pub mod ray;
+mod render;
+pub mod sampler;
+pub mod scene;
+pub mod texture;
+pub mod tonemap;
+pub mod tracer;
+pub mod transform;
+mod util;
+pub mod vec3;
+pub mod world;
+
+pub use aabb::Aabb;
+pub use bvh::{BvhNode, BvhOptions, LinearBvh};
+pub use camera::Camera;
+pub use environment::{
+    ConstantEnvironment, Environment, EnvironmentHandle, EnvironmentMap, GradientEnvironment,
+};
+pub use film::Film;
+pub use hit::{
+    HitRecord, Hitable, HitableHandle, MovingSphere, Plane, Quad, Sphere, Triangle, TriangleMesh,
+};
+pub use instance::{AnimatedInstance, Instance};
+pub use material::{Dielectric, DiffuseLight, Lambertian, Material, MaterialHandle, Metal};
+pub use ray::Ray;
+pub use sampler::{
+    HaltonSampler, IndependentSampler, Sampler, SamplerHandle, SamplerKind, SobolSampler,
+    StratifiedSampler,
+};
+pub use scene::Scene;
+pub use texture::{
+    CheckerTexture, ConstantTexture, ImageTexture, NoiseTexture, Perlin, Texture, TextureHandle,
+    WrapMode,
+};
+pub use tonemap::{ToneMap, ToneMapOperator};
+pub use tracer::{AdaptiveOptions, Progress, Tracer};
+pub use transform::{AnimatedTransform, Mat3, Mat4, Onb, Quat, Transform, Trs};
+pub use vec3::Vec3;
+pub use world::World;
@@ -0,0 +1,240 @@
+use crate::hit::{HitRecord, Hitable, MeshTriangle, Quad, Sphere, Triangle};
+use crate::material::MaterialHandle;
+use crate::ray::Ray;
+use crate::transform::Onb;
+use crate::vec3::Vec3;
+
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+/// A point on a light chosen for next event estimation.
+#[derive(Debug, Copy, Clone)]
+pub struct LightSample {
+    /// Unit direction from the shaded point towards the light.
+    pub direction: Vec3,
+    pub distance: f32,
+    /// Density of the direction per unit solid angle.
+    pub pdf: f32,
+    pub radiance: Vec3,
+}
+
+/// Tells lights apart by their address, so a hit can be traced back to the
+/// light that `World::sample_light` would have to pick to find it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct LightId(usize);
+
+impl LightId {
+    pub fn of(light: &dyn Light) -> Self {
+        LightId(light as *const dyn Light as *const () as usize)
+    }
+}
+
+/// An emissive shape that can be sampled directly. Lights don't move, so the
+/// rays used to sample them are all at time zero.
+pub trait Light {
+    /// Maps the point `u` of the unit square to a point on the light seen from `origin`.
+    fn sample(&self, origin: Vec3, u: (f32, f32)) -> Option<LightSample>;
+
+    /// Solid angle density of `sample` picking `direction` from `origin`, zero
+    /// if the light isn't visible in that direction.
+    fn pdf(&self, origin: Vec3, direction: Vec3) -> f32;
+}
+
+impl Light for Sphere {
+    fn sample(&self, origin: Vec3, u: (f32, f32)) -> Option<LightSample> {
+        // Uniformly sample the cone of directions subtended by the sphere
+        let to_center = self.center - origin;
+        let distance_squared = to_center.squared_length();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return None;
+        }
+        let cone = cone_solid_angle(radius_squared / distance_squared);
+
+        let (r1, r2) = u;
+        let one_minus_cos_theta = r2 * cone / (2.0 * PI);
+        let cos_theta = 1.0 - one_minus_cos_theta;
+        let sin_theta = (one_minus_cos_theta * (2.0 - one_minus_cos_theta))
+            .max(0.0)
+            .sqrt();
+        let phi = 2.0 * PI * r1;
+
+        let direction = Onb::from_w(to_center).local(Vec3::new(
+            phi.cos() * sin_theta,
+            phi.sin() * sin_theta,
+            cos_theta,
+        ));
+
+        let ray = Ray {
+            origin,
+            direction,
+            time: 0.0,
+        };
+        let hit = self.hit(&ray, 0.0, f32::MAX)?;
+
+        Some(LightSample {
+            direction,
+            distance: hit.t,
+            pdf: 1.0 / cone,
+            radiance: hit.material.emitted(&ray, &hit),
+        })
+    }
+
+    fn pdf(&self, origin: Vec3, direction: Vec3) -> f32 {
+        if self
+            .hit(
+                &Ray {
+                    origin,
+                    direction,
+                    time: 0.0,
+                },
+                0.001,
+                f32::MAX,
+            )
+            .is_none()
+        {
+            return 0.0;
+        }
+
+        // `sample` gives up inside the sphere
+        let distance_squared = (self.center - origin).squared_length();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return 0.0;
+        }
+        1.0 / cone_solid_angle(radius_squared / distance_squared)
+    }
+}
+
+/// Solid angle of the cone around a sphere whose squared radius is `ratio`
+/// times the squared distance to its centre. `1 - cos(theta_max)` is written
+/// without the cancellation that makes it zero for small, distant spheres.
+fn cone_solid_angle(ratio: f32) -> f32 {
+    2.0 * PI * ratio / (1.0 + (1.0 - ratio).sqrt())
+}
+
+impl Light for Quad {
+    fn sample(&self, origin: Vec3, u: (f32, f32)) -> Option<LightSample> {
+        let (s, t) = u;
+        let point = self.corner + s * self.u + t * self.v;
+        let area = Vec3::cross(self.u, self.v).length();
+
+        sample_area(origin, point, self.normal(), area, &self.material)
+    }
+
+    fn pdf(&self, origin: Vec3, direction: Vec3) -> f32 {
+        let area = Vec3::cross(self.u, self.v).length();
+
+        area_pdf(self, origin, direction, area)
+    }
+}
+
+impl Light for Triangle {
+    fn sample(&self, origin: Vec3, u: (f32, f32)) -> Option<LightSample> {
+        let [p0, p1, p2] = self.vertices;
+        let n = Vec3::cross(p1 - p0, p2 - p0);
+
+        sample_area(
+            origin,
+            sample_triangle(p0, p1, p2, u),
+            n.unit(),
+            0.5 * n.length(),
+            &self.material,
+        )
+    }
+
+    fn pdf(&self, origin: Vec3, direction: Vec3) -> f32 {
+        let [p0, p1, p2] = self.vertices;
+        let area = 0.5 * Vec3::cross(p1 - p0, p2 - p0).length();
+
+        area_pdf(self, origin, direction, area)
+    }
+}
+
+impl Light for MeshTriangle {
+    fn sample(&self, origin: Vec3, u: (f32, f32)) -> Option<LightSample> {
+        let [p0, p1, p2] = self.vertices();
+        let n = Vec3::cross(p1 - p0, p2 - p0);
+
+        sample_area(
+            origin,
+            sample_triangle(p0, p1, p2, u),
+            n.unit(),
+            0.5 * n.length(),
+            self.material(),
+        )
+    }
+
+    fn pdf(&self, origin: Vec3, direction: Vec3) -> f32 {
+        let [p0, p1, p2] = self.vertices();
+        let area = 0.5 * Vec3::cross(p1 - p0, p2 - p0).length();
+
+        area_pdf(self, origin, direction, area)
+    }
+}
+
+/// Turns a point sampled uniformly on a flat light into a solid angle sample.
+fn sample_area(
+    origin: Vec3,
+    point: Vec3,
+    normal: Vec3,
+    area: f32,
+    material: &Arc<MaterialHandle>,
+) -> Option<LightSample> {
+    let to_light = point - origin;
+    let distance = to_light.length();
+    let direction = to_light / distance;
+    let cosine = Vec3::dot(direction, normal).abs();
+    if cosine < 1e-6 || distance < 1e-6 {
+        return None;
+    }
+
+    let ray = Ray {
+        origin,
+        direction,
+        time: 0.0,
+    };
+    let hit = HitRecord {
+        t: distance,
+        point,
+        normal,
+        u: 0.0,
+        v: 0.0,
+        material: material.clone(),
+        light: None,
+    };
+
+    Some(LightSample {
+        direction,
+        distance,
+        pdf: distance * distance / (cosine * area),
+        radiance: material.emitted(&ray, &hit),
+    })
+}
+
+fn area_pdf<T: Hitable>(light: &T, origin: Vec3, direction: Vec3, area: f32) -> f32 {
+    match light.hit(
+        &Ray {
+            origin,
+            direction,
+            time: 0.0,
+        },
+        0.001,
+        f32::MAX,
+    ) {
+        Some(hit) => {
+            let distance_squared = hit.t * hit.t * direction.squared_length();
+            let cosine = (Vec3::dot(direction, hit.normal) / direction.length()).abs();
+            distance_squared / (cosine * area)
+        }
+        None => 0.0,
+    }
+}
+
+fn sample_triangle(p0: Vec3, p1: Vec3, p2: Vec3, (u, v): (f32, f32)) -> Vec3 {
+    let su = u.sqrt();
+    let b0 = 1.0 - su;
+    let b1 = v * su;
+
+    b0 * p0 + b1 * p1 + (1.0 - b0 - b1) * p2
+}
@@ -1,54 +1,193 @@
 use crate::hit::HitRecord;
 use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::texture::{ConstantTexture, TextureHandle};
 use crate::util;
 use crate::vec3::Vec3;
 
-use rand::Rng;
+use std::f32::consts::PI;
+use std::sync::Arc;
 
 pub trait Material {
-    fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<(Ray, Vec3)>;
+    /// Picks the direction `ray` continues in after the hit with `sampler`, and
+    /// the attenuation along it.
+    fn scatter(&self, ray: &Ray, hit: &HitRecord, sampler: &mut dyn Sampler)
+        -> Option<(Ray, Vec3)>;
+
+    /// Radiance emitted from the hit point back along `ray`.
+    fn emitted(&self, _ray: &Ray, _hit: &HitRecord) -> Vec3 {
+        Vec3::zero()
+    }
+
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    /// BSDF times the cosine term for light arriving from `direction` and leaving
+    /// along `-ray.direction`, or `None` if lights can't be sampled for this material.
+    fn eval(&self, _ray: &Ray, _hit: &HitRecord, _direction: Vec3) -> Option<Vec3> {
+        None
+    }
+
+    /// Solid angle density of `scatter` picking `direction`, zero for materials
+    /// that scatter into a single direction.
+    fn pdf(&self, _ray: &Ray, _hit: &HitRecord, _direction: Vec3) -> f32 {
+        0.0
+    }
 }
 
 pub type MaterialHandle = Box<dyn Material + Send + Sync>;
 
 pub struct Lambertian {
-    pub albedo: Vec3,
+    pub albedo: Arc<TextureHandle>,
+}
+
+impl Lambertian {
+    pub fn new(albedo: Vec3) -> Self {
+        Lambertian {
+            albedo: ConstantTexture::handle(albedo),
+        }
+    }
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _ray: &Ray, hit: &HitRecord) -> Option<(Ray, Vec3)> {
-        let target = hit.point + hit.normal + util::random_in_unit_sphere();
+    fn scatter(
+        &self,
+        ray: &Ray,
+        hit: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Ray, Vec3)> {
+        // Offsetting the normal by a unit vector gives a cosine distribution,
+        // which matches `eval`
+        let normal = facing_normal(ray, hit);
+        let mut direction = normal + util::uniform_sphere(sampler.get_2d());
+        if direction.squared_length() < 1e-8 {
+            direction = normal;
+        }
         Some((
             Ray {
                 origin: hit.point,
-                direction: target - hit.point,
+                direction,
+                time: ray.time,
             },
-            self.albedo,
+            self.albedo.value(hit.u, hit.v, hit.point),
         ))
     }
+
+    fn eval(&self, ray: &Ray, hit: &HitRecord, direction: Vec3) -> Option<Vec3> {
+        let albedo = self.albedo.value(hit.u, hit.v, hit.point);
+        Some(albedo * self.pdf(ray, hit, direction))
+    }
+
+    fn pdf(&self, ray: &Ray, hit: &HitRecord, direction: Vec3) -> f32 {
+        Vec3::dot(facing_normal(ray, hit), direction.unit()).max(0.0) / PI
+    }
 }
 
+/// Mirror reflection blurred by `fuzz`: the reflected direction is moved by a
+/// random point in a ball of radius `fuzz`. The back of the surface absorbs
+/// everything, blurred directions are kept even if they point below it.
 pub struct Metal {
-    pub albedo: Vec3,
+    pub albedo: Arc<TextureHandle>,
     pub fuzz: f32,
 }
 
-impl Material for Metal {
-    fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<(Ray, Vec3)> {
-        let fuzz = if self.fuzz < 1.0 { self.fuzz } else { 1.0 };
+impl Metal {
+    pub fn new(albedo: Vec3, fuzz: f32) -> Self {
+        Metal {
+            albedo: ConstantTexture::handle(albedo),
+            fuzz,
+        }
+    }
+
+    /// `None` for a perfect mirror.
+    fn fuzz(&self) -> Option<f32> {
+        let fuzz = self.fuzz.min(1.0);
+        if fuzz > 0.0 {
+            Some(fuzz)
+        } else {
+            None
+        }
+    }
+
+    /// Mirror direction of `ray`, `None` if it hit the back of the surface.
+    fn reflected(ray: &Ray, hit: &HitRecord) -> Option<Vec3> {
         let reflected = reflect(ray.direction.unit(), hit.normal);
         if Vec3::dot(reflected, hit.normal) > 0.0 {
-            Some((
-                Ray {
-                    origin: hit.point,
-                    direction: reflected + util::random_in_unit_sphere() * fuzz,
-                },
-                self.albedo,
-            ))
+            Some(reflected)
         } else {
             None
         }
     }
+
+    /// Solid angle density of `reflected + fuzz * p` pointing along
+    /// `direction`, for `p` uniform in the unit ball: the volume of the ball
+    /// around the tip of the unit vector `reflected` along the ray, in
+    /// spherical coordinates, over the volume of the ball.
+    fn fuzz_pdf(fuzz: f32, reflected: Vec3, direction: Vec3) -> f32 {
+        let cosine = Vec3::dot(reflected, direction.unit());
+        let discriminant = fuzz * fuzz - (1.0 - cosine * cosine).max(0.0);
+        if discriminant <= 0.0 {
+            return 0.0;
+        }
+
+        // Distances along the ray where it enters and leaves the ball
+        let root = discriminant.sqrt();
+        let (near, far) = ((cosine - root).max(0.0), cosine + root);
+        if far <= near {
+            return 0.0;
+        }
+        (far - near) * (far * far + far * near + near * near) / (4.0 * PI * fuzz.powi(3))
+    }
+}
+
+impl Material for Metal {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        hit: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Ray, Vec3)> {
+        let reflected = Metal::reflected(ray, hit)?;
+        let direction = match self.fuzz() {
+            Some(fuzz) => {
+                let in_ball = util::uniform_sphere(sampler.get_2d()) * sampler.get_1d().cbrt();
+                reflected + fuzz * in_ball
+            }
+            None => reflected,
+        };
+
+        Some((
+            Ray {
+                origin: hit.point,
+                direction,
+                time: ray.time,
+            },
+            self.albedo.value(hit.u, hit.v, hit.point),
+        ))
+    }
+
+    fn eval(&self, ray: &Ray, hit: &HitRecord, direction: Vec3) -> Option<Vec3> {
+        let fuzz = self.fuzz()?;
+        let reflected = match Metal::reflected(ray, hit) {
+            Some(reflected) => reflected,
+            None => return Some(Vec3::zero()),
+        };
+
+        let albedo = self.albedo.value(hit.u, hit.v, hit.point);
+        Some(albedo * Metal::fuzz_pdf(fuzz, reflected, direction))
+    }
+
+    fn pdf(&self, ray: &Ray, hit: &HitRecord, direction: Vec3) -> f32 {
+        let fuzz = match self.fuzz() {
+            Some(fuzz) => fuzz,
+            None => return 0.0,
+        };
+        match Metal::reflected(ray, hit) {
+            Some(reflected) => Metal::fuzz_pdf(fuzz, reflected, direction),
+            None => 0.0,
+        }
+    }
 }
 
 pub struct Dielectric {
@@ -56,10 +195,13 @@ pub struct Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<(Ray, Vec3)> {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        hit: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Ray, Vec3)> {
         assert_ne!(self.refraction_idx, 0.0);
-        let mut rng = util::local_rng();
-
         let reflected = reflect(ray.direction, hit.normal);
 
         let (outward_normal, ni_over_nt, cosine) = if Vec3::dot(ray.direction, hit.normal) > 0.0 {
@@ -77,17 +219,67 @@ impl Material for Dielectric {
         };
 
         let direction = refract(ray.direction, outward_normal, ni_over_nt)
-            .filter(|_| rng.gen_range(0.0..1.0) > schlick(cosine, self.refraction_idx))
+            .filter(|_| sampler.get_1d() > schlick(cosine, self.refraction_idx))
             .unwrap_or(reflected);
 
         Some((
             Ray {
                 origin: hit.point,
                 direction,
+                time: ray.time,
             },
             Vec3::one(),
         ))
     }
+
+    // Both reflection and refraction are perfectly specular
+    fn eval(&self, _ray: &Ray, _hit: &HitRecord, _direction: Vec3) -> Option<Vec3> {
+        None
+    }
+
+    fn pdf(&self, _ray: &Ray, _hit: &HitRecord, _direction: Vec3) -> f32 {
+        0.0
+    }
+}
+
+/// Emits `color * intensity` and absorbs all incoming light.
+pub struct DiffuseLight {
+    pub color: Vec3,
+    pub intensity: f32,
+    /// Only emit on the side the surface normal points to.
+    pub one_sided: bool,
+}
+
+impl Material for DiffuseLight {
+    fn scatter(
+        &self,
+        _ray: &Ray,
+        _hit: &HitRecord,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<(Ray, Vec3)> {
+        None
+    }
+
+    fn emitted(&self, ray: &Ray, hit: &HitRecord) -> Vec3 {
+        if self.one_sided && Vec3::dot(ray.direction, hit.normal) > 0.0 {
+            Vec3::zero()
+        } else {
+            self.color * self.intensity
+        }
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.intensity > 0.0 && self.color != Vec3::zero()
+    }
+}
+
+/// The surface normal flipped to the side `ray` arrives from.
+pub fn facing_normal(ray: &Ray, hit: &HitRecord) -> Vec3 {
+    if Vec3::dot(ray.direction, hit.normal) > 0.0 {
+        -hit.normal
+    } else {
+        hit.normal
+    }
 }
 
 pub fn reflect(v: Vec3, n: Vec3) -> Vec3 {
@@ -0,0 +1,546 @@
+use crate::bvh::LinearBvh;
+use crate::hit::{HitableHandle, MeshFace, TriangleMesh};
+use crate::material::{Dielectric, DiffuseLight, Lambertian, MaterialHandle, Metal};
+use crate::texture::{ConstantTexture, ImageTexture, TextureHandle, WrapMode};
+use crate::vec3::Vec3;
+
+use image::ImageError;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    Empty {
+        path: PathBuf,
+    },
+    Texture {
+        path: PathBuf,
+        source: Box<ImageError>,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            ObjError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+            ObjError::Empty { path } => write!(f, "{}: no faces", path.display()),
+            ObjError::Texture { path, source } => write!(f, "{}: {}", path.display(), source),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ObjError::Io { source, .. } => Some(source),
+            ObjError::Texture { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// A named range of faces, started by a `g` or `o` statement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjGroup {
+    pub name: String,
+    pub faces: Range<usize>,
+}
+
+pub struct ObjModel {
+    pub mesh: TriangleMesh,
+    pub groups: Vec<ObjGroup>,
+}
+
+/// Loads an OBJ file with its material libraries and wraps the triangles in a BVH.
+pub fn load_obj<P: AsRef<Path>>(path: P) -> Result<HitableHandle, ObjError> {
+    let path = path.as_ref();
+    let model = load_obj_model(path)?;
+    if model.mesh.faces.is_empty() {
+        return Err(ObjError::Empty {
+            path: path.to_owned(),
+        });
+    }
+
+    let mut triangles = TriangleMesh::triangles(&Arc::new(model.mesh));
+    Ok(Box::new(LinearBvh::new(&mut triangles[..], 0.0, 0.0)))
+}
+
+pub fn load_obj_model<P: AsRef<Path>>(path: P) -> Result<ObjModel, ObjError> {
+    let path = path.as_ref();
+    let source = read_file(path)?;
+    let mut parser = ObjParser::new();
+
+    for (line_idx, line) in logical_lines(&source) {
+        parser
+            .parse_line(&line)
+            .map_err(|message| ObjError::Parse {
+                path: path.to_owned(),
+                line: line_idx,
+                message,
+            })?;
+        if let Some(libs) = parser.pending_libs.take() {
+            for lib in libs {
+                let lib_path = path.parent().unwrap_or_else(|| Path::new("")).join(lib);
+                parser.materials.extend(load_mtl(lib_path)?);
+            }
+        }
+    }
+
+    Ok(parser.finish())
+}
+
+/// Parses an MTL file, mapping every material onto the closest material we support.
+pub fn load_mtl<P: AsRef<Path>>(path: P) -> Result<HashMap<String, Arc<MaterialHandle>>, ObjError> {
+    let path = path.as_ref();
+    let source = read_file(path)?;
+
+    let mut materials = HashMap::new();
+    let mut textures: HashMap<PathBuf, Arc<TextureHandle>> = HashMap::new();
+    let mut current: Option<(String, MtlMaterial)> = None;
+
+    for (line_idx, line) in logical_lines(&source) {
+        let parse_error = |message: String| ObjError::Parse {
+            path: path.to_owned(),
+            line: line_idx,
+            message,
+        };
+
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            if args.is_empty() {
+                return Err(parse_error("expected a material name".to_owned()));
+            }
+            if let Some((name, material)) = current.take() {
+                materials.insert(name, material.build());
+            }
+            current = Some((args.join(" "), MtlMaterial::default()));
+            continue;
+        }
+
+        let material = match &mut current {
+            Some((_, material)) => material,
+            None => {
+                return Err(parse_error(format!(
+                    "`{}` outside of a `newmtl` block",
+                    keyword
+                )))
+            }
+        };
+
+        let number = || parse_floats::<1>(keyword, &args).map(|[v]| v);
+        match keyword {
+            "Kd" => material.diffuse = parse_color(keyword, &args).map_err(parse_error)?,
+            "Ks" => material.specular = parse_color(keyword, &args).map_err(parse_error)?,
+            "Ke" => material.emission = parse_color(keyword, &args).map_err(parse_error)?,
+            "Ns" => material.shininess = number().map_err(parse_error)?,
+            "Ni" => material.ior = number().map_err(parse_error)?,
+            "d" => material.dissolve = number().map_err(parse_error)?,
+            "Tr" => material.dissolve = 1.0 - number().map_err(parse_error)?,
+            "illum" => {
+                material.illum = match args.as_slice() {
+                    [model] => model.parse().map_err(|_| {
+                        parse_error(format!("invalid illumination model `{}`", model))
+                    })?,
+                    _ => return Err(parse_error("expected an illumination model".to_owned())),
+                }
+            }
+            "map_Kd" => {
+                // Options such as `-s` come before the file name, they are ignored
+                let file = match args.last() {
+                    Some(file) => path.parent().unwrap_or_else(|| Path::new("")).join(file),
+                    None => return Err(parse_error("expected a texture file".to_owned())),
+                };
+                let texture = match textures.get(&file) {
+                    Some(texture) => texture.clone(),
+                    None => {
+                        let image =
+                            ImageTexture::open(&file, WrapMode::Repeat).map_err(|source| {
+                                ObjError::Texture {
+                                    path: file.clone(),
+                                    source: Box::new(source),
+                                }
+                            })?;
+                        let texture: Arc<TextureHandle> = Arc::new(Box::new(image));
+                        textures.insert(file, texture.clone());
+                        texture
+                    }
+                };
+                material.diffuse_map = Some(texture);
+            }
+            // The other texture maps and the remaining statements have no equivalent yet
+            _ => {}
+        }
+    }
+
+    if let Some((name, material)) = current.take() {
+        materials.insert(name, material.build());
+    }
+
+    Ok(materials)
+}
+
+struct MtlMaterial {
+    diffuse: Vec3,
+    /// Used instead of `diffuse` when present
+    diffuse_map: Option<Arc<TextureHandle>>,
+    specular: Vec3,
+    emission: Vec3,
+    shininess: f32,
+    ior: f32,
+    dissolve: f32,
+    illum: u32,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        MtlMaterial {
+            diffuse: Vec3::new(0.8, 0.8, 0.8),
+            diffuse_map: None,
+            specular: Vec3::zero(),
+            emission: Vec3::zero(),
+            shininess: 0.0,
+            ior: 1.5,
+            dissolve: 1.0,
+            illum: 2,
+        }
+    }
+}
+
+impl MtlMaterial {
+    fn build(self) -> Arc<MaterialHandle> {
+        let is_glass = matches!(self.illum, 4 | 6 | 7 | 9) || self.dissolve < 1.0;
+        let is_metal = matches!(self.illum, 3 | 5)
+            || (self.diffuse == Vec3::zero() && self.specular != Vec3::zero());
+
+        if self.emission != Vec3::zero() {
+            Arc::new(Box::new(DiffuseLight {
+                color: self.emission,
+                intensity: 1.0,
+                one_sided: false,
+            }))
+        } else if is_glass {
+            Arc::new(Box::new(Dielectric {
+                refraction_idx: self.ior,
+            }))
+        } else if is_metal {
+            // Roughness of the Blinn-Phong lobe with exponent `Ns`
+            let fuzz = (2.0 / (self.shininess.max(0.0) + 2.0)).sqrt();
+            Arc::new(Box::new(Metal::new(self.specular, fuzz)))
+        } else {
+            let diffuse = self.diffuse;
+            Arc::new(Box::new(Lambertian {
+                albedo: self
+                    .diffuse_map
+                    .unwrap_or_else(|| ConstantTexture::handle(diffuse)),
+            }))
+        }
+    }
+}
+
+struct ObjParser {
+    mesh: TriangleMesh,
+    groups: Vec<ObjGroup>,
+    materials: HashMap<String, Arc<MaterialHandle>>,
+    material_ids: HashMap<String, u32>,
+    current_material: Option<u32>,
+    pending_libs: Option<Vec<String>>,
+}
+
+impl ObjParser {
+    fn new() -> Self {
+        ObjParser {
+            mesh: TriangleMesh::default(),
+            groups: vec![ObjGroup {
+                name: "default".to_owned(),
+                faces: 0..0,
+            }],
+            materials: HashMap::new(),
+            material_ids: HashMap::new(),
+            current_material: None,
+            pending_libs: None,
+        }
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), String> {
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => return Ok(()),
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => {
+                // An optional `w` component or vertex colours may follow
+                let xyz = match args.len() {
+                    3 | 4 | 6 | 7 => parse_floats::<3>(keyword, &args[..3])?,
+                    _ => return Err(format!("expected 3 coordinates after `{}`", keyword)),
+                };
+                self.mesh.positions.push(Vec3::new(xyz[0], xyz[1], xyz[2]));
+            }
+            "vn" => {
+                let xyz = parse_floats::<3>(keyword, &args)?;
+                self.mesh
+                    .normals
+                    .push(Vec3::new(xyz[0], xyz[1], xyz[2]).unit());
+            }
+            "vt" => {
+                let uv = match args.len() {
+                    1 => [parse_floats::<1>(keyword, &args)?[0], 0.0],
+                    2 | 3 => parse_floats::<2>(keyword, &args[..2])?,
+                    _ => return Err(format!("expected 2 coordinates after `{}`", keyword)),
+                };
+                self.mesh.uvs.push(uv);
+            }
+            "f" => self.parse_face(&args)?,
+            "g" | "o" => {
+                let name = if args.is_empty() {
+                    "default".to_owned()
+                } else {
+                    args.join(" ")
+                };
+                self.start_group(name);
+            }
+            "usemtl" => {
+                if args.is_empty() {
+                    return Err("expected a material name".to_owned());
+                }
+                let name = args.join(" ");
+                let id = self.material_id(&name)?;
+                self.current_material = Some(id);
+            }
+            "mtllib" => {
+                if args.is_empty() {
+                    return Err("expected a material library".to_owned());
+                }
+                self.pending_libs = Some(args.iter().map(|lib| lib.to_string()).collect());
+            }
+            // Smoothing groups, lines and points don't affect rendering
+            "s" | "l" | "p" => {}
+            _ => return Err(format!("unknown statement `{}`", keyword)),
+        }
+
+        Ok(())
+    }
+
+    fn parse_face(&mut self, args: &[&str]) -> Result<(), String> {
+        if args.len() < 3 {
+            return Err(format!(
+                "a face needs at least 3 vertices, found {}",
+                args.len()
+            ));
+        }
+
+        let mut vertices = Vec::with_capacity(args.len());
+        for arg in args {
+            vertices.push(self.parse_face_vertex(arg)?);
+        }
+
+        let has_normals = vertices.iter().all(|(_, _, n)| n.is_some());
+        let has_uvs = vertices.iter().all(|(_, uv, _)| uv.is_some());
+        let material = match self.current_material {
+            Some(id) => id,
+            None => self.material_id("")?,
+        };
+
+        // Fan triangulation around the first vertex
+        for i in 1..vertices.len() - 1 {
+            let tri = [vertices[0], vertices[i], vertices[i + 1]];
+            self.mesh.faces.push(MeshFace {
+                positions: [tri[0].0, tri[1].0, tri[2].0],
+                normals: if has_normals {
+                    Some([tri[0].2.unwrap(), tri[1].2.unwrap(), tri[2].2.unwrap()])
+                } else {
+                    None
+                },
+                uvs: if has_uvs {
+                    Some([tri[0].1.unwrap(), tri[1].1.unwrap(), tri[2].1.unwrap()])
+                } else {
+                    None
+                },
+                material,
+            });
+        }
+        self.groups.last_mut().unwrap().faces.end = self.mesh.faces.len();
+
+        Ok(())
+    }
+
+    /// Parses `v`, `v/vt`, `v//vn` or `v/vt/vn` into zero-based indices.
+    fn parse_face_vertex(&self, arg: &str) -> Result<(u32, Option<u32>, Option<u32>), String> {
+        let mut parts = arg.split('/');
+        let position = resolve_index(parts.next(), self.mesh.positions.len(), "vertex", arg)?
+            .ok_or_else(|| format!("missing vertex index in `{}`", arg))?;
+        let uv = resolve_index(parts.next(), self.mesh.uvs.len(), "texture coordinate", arg)?;
+        let normal = resolve_index(parts.next(), self.mesh.normals.len(), "normal", arg)?;
+        if parts.next().is_some() {
+            return Err(format!("invalid face vertex `{}`", arg));
+        }
+
+        Ok((position, uv, normal))
+    }
+
+    fn material_id(&mut self, name: &str) -> Result<u32, String> {
+        if let Some(&id) = self.material_ids.get(name) {
+            return Ok(id);
+        }
+
+        let material = if name.is_empty() {
+            Arc::new(Box::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))) as MaterialHandle)
+        } else {
+            self.materials
+                .get(name)
+                .cloned()
+                .ok_or_else(|| format!("unknown material `{}`", name))?
+        };
+
+        let id = self.mesh.materials.len() as u32;
+        self.mesh.materials.push(material);
+        self.material_ids.insert(name.to_owned(), id);
+        Ok(id)
+    }
+
+    fn start_group(&mut self, name: String) {
+        let start = self.mesh.faces.len();
+        let last = self.groups.last_mut().unwrap();
+        if last.faces.is_empty() {
+            last.name = name;
+            last.faces = start..start;
+        } else {
+            self.groups.push(ObjGroup {
+                name,
+                faces: start..start,
+            });
+        }
+    }
+
+    fn finish(mut self) -> ObjModel {
+        self.groups.retain(|group| !group.faces.is_empty());
+        ObjModel {
+            mesh: self.mesh,
+            groups: self.groups,
+        }
+    }
+}
+
+/// Converts a one-based (or negative, relative to the end) OBJ index into a zero-based one.
+fn resolve_index(
+    part: Option<&str>,
+    count: usize,
+    kind: &str,
+    arg: &str,
+) -> Result<Option<u32>, String> {
+    let part = match part {
+        Some(part) if !part.is_empty() => part,
+        _ => return Ok(None),
+    };
+    let idx: i64 = part
+        .parse()
+        .map_err(|_| format!("invalid {} index `{}` in `{}`", kind, part, arg))?;
+
+    let resolved = if idx > 0 {
+        idx - 1
+    } else if idx < 0 {
+        count as i64 + idx
+    } else {
+        return Err(format!("{} index 0 in `{}`", kind, arg));
+    };
+
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(format!(
+            "{} index {} out of range in `{}`, {} defined so far",
+            kind, idx, arg, count
+        ));
+    }
+
+    Ok(Some(resolved as u32))
+}
+
+fn parse_floats<const N: usize>(keyword: &str, args: &[&str]) -> Result<[f32; N], String> {
+    if args.len() != N {
+        return Err(format!(
+            "expected {} numbers after `{}`, found {}",
+            N,
+            keyword,
+            args.len()
+        ));
+    }
+
+    let mut res = [0.0; N];
+    for (value, arg) in res.iter_mut().zip(args) {
+        *value = arg
+            .parse()
+            .map_err(|_| format!("invalid number `{}` after `{}`", arg, keyword))?;
+    }
+    Ok(res)
+}
+
+fn parse_color(keyword: &str, args: &[&str]) -> Result<Vec3, String> {
+    match args.len() {
+        // A single value is a grey level
+        1 => {
+            let [v] = parse_floats::<1>(keyword, args)?;
+            Ok(Vec3::new(v, v, v))
+        }
+        _ => {
+            let [r, g, b] = parse_floats::<3>(keyword, args)?;
+            Ok(Vec3::new(r, g, b))
+        }
+    }
+}
+
+fn read_file(path: &Path) -> Result<String, ObjError> {
+    fs::read_to_string(path).map_err(|source| ObjError::Io {
+        path: path.to_owned(),
+        source,
+    })
+}
+
+/// Yields lines with comments stripped and `\` continuations joined, along with
+/// the one-based number of the line they start on.
+fn logical_lines(source: &str) -> impl Iterator<Item = (usize, String)> + '_ {
+    let mut lines = source.lines().enumerate();
+    std::iter::from_fn(move || {
+        let (idx, first) = lines.next()?;
+        let mut line = String::from(first);
+        while line.ends_with('\\') {
+            line.pop();
+            match lines.next() {
+                Some((_, next)) => {
+                    line.push(' ');
+                    line.push_str(next);
+                }
+                None => break,
+            }
+        }
+        if let Some(comment) = line.find('#') {
+            line.truncate(comment);
+        }
+        Some((idx + 1, line))
+    })
+}
@@ -0,0 +1,254 @@
+use crate::tracer::Tracer;
+use crate::vec3::Vec3;
+
+use exr::prelude::{
+    f16, AnyChannel, AnyChannels, Encoding, FlatSamples, Image, Layer as ExrLayer, LayerAttributes,
+    SmallVec, WritableImage,
+};
+use image::codecs::hdr::HdrEncoder;
+use image::{GrayImage, ImageError, Rgb, RgbaImage};
+use std::convert::TryInto;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum OutputError {
+    Io(io::Error),
+    Image(ImageError),
+    Exr(exr::error::Error),
+    UnknownFormat(PathBuf),
+}
+
+impl fmt::Display for OutputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputError::Io(e) => write!(f, "{}", e),
+            OutputError::Image(e) => write!(f, "{}", e),
+            OutputError::Exr(e) => write!(f, "{}", e),
+            OutputError::UnknownFormat(path) => {
+                write!(f, "unknown image format for {}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for OutputError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            OutputError::Io(e) => Some(e),
+            OutputError::Image(e) => Some(e),
+            OutputError::Exr(e) => Some(e),
+            OutputError::UnknownFormat(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for OutputError {
+    fn from(e: io::Error) -> Self {
+        OutputError::Io(e)
+    }
+}
+
+impl From<ImageError> for OutputError {
+    fn from(e: ImageError) -> Self {
+        OutputError::Image(e)
+    }
+}
+
+impl From<exr::error::Error> for OutputError {
+    fn from(e: exr::error::Error) -> Self {
+        OutputError::Exr(e)
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum ExrPixelType {
+    #[default]
+    Half,
+    Float,
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SaveOptions {
+    pub exr_pixel_type: ExrPixelType,
+}
+
+/// A named set of linear RGB pixels, rows from top to bottom.
+pub struct Layer<'a> {
+    /// Prefix of the channel names, the empty name is the main image.
+    pub name: &'a str,
+    pub pixels: &'a [Vec3],
+}
+
+/// Saves the current image, the format is picked from the file extension.
+///
+/// `.exr`, `.hdr` and `.pfm` files keep the linear radiance, every other format
+/// is written through the `image` crate as 8-bit RGBA.
+pub fn save_image<P: AsRef<Path>>(tracer: &Tracer, path: P) -> Result<(), OutputError> {
+    save_image_with(tracer, path, &SaveOptions::default())
+}
+
+pub fn save_image_with<P: AsRef<Path>>(
+    tracer: &Tracer,
+    path: P,
+    options: &SaveOptions,
+) -> Result<(), OutputError> {
+    let path = path.as_ref();
+    let (width, height) = (tracer.width(), tracer.height());
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase())
+        .ok_or_else(|| OutputError::UnknownFormat(path.to_owned()))?;
+
+    match extension.as_str() {
+        "exr" => {
+            let radiance = tracer.radiance();
+            let layers = [Layer {
+                name: "",
+                pixels: &radiance,
+            }];
+            write_exr(path, width, height, &layers, options.exr_pixel_type)
+        }
+        "hdr" => write_hdr(path, width, height, &tracer.radiance()),
+        "pfm" => write_pfm(path, width, height, &tracer.radiance()),
+        _ => {
+            let mut buf = vec![0; (width * height * 4).try_into().unwrap()];
+            tracer.flush(&mut buf);
+
+            let img = RgbaImage::from_vec(width, height, buf).unwrap();
+
+            Ok(img.save(path)?)
+        }
+    }
+}
+
+/// Saves the number of samples of every pixel as a grey image, to see where
+/// adaptive sampling spent its time.
+///
+/// `.exr`, `.hdr` and `.pfm` files hold the counts themselves, every other
+/// format is scaled so that the largest count is white.
+pub fn save_sample_counts<P: AsRef<Path>>(
+    tracer: &Tracer,
+    path: P,
+    options: &SaveOptions,
+) -> Result<(), OutputError> {
+    let path = path.as_ref();
+    let (width, height) = (tracer.width(), tracer.height());
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase())
+        .ok_or_else(|| OutputError::UnknownFormat(path.to_owned()))?;
+
+    let counts = tracer.sample_counts();
+    let pixels: Vec<Vec3> = counts
+        .iter()
+        .map(|&count| Vec3::one() * count as f32)
+        .collect();
+
+    match extension.as_str() {
+        "exr" => {
+            let layers = [Layer {
+                name: "",
+                pixels: &pixels,
+            }];
+            write_exr(path, width, height, &layers, options.exr_pixel_type)
+        }
+        "hdr" => write_hdr(path, width, height, &pixels),
+        "pfm" => write_pfm(path, width, height, &pixels),
+        _ => {
+            let max = counts.iter().copied().max().unwrap_or(0).max(1) as f32;
+            let buf = counts
+                .iter()
+                .map(|&count| (255.0 * count as f32 / max).round() as u8)
+                .collect();
+
+            let img = GrayImage::from_vec(width, height, buf).unwrap();
+
+            Ok(img.save(path)?)
+        }
+    }
+}
+
+/// Writes an OpenEXR file with the layers as `<name>.R`, `<name>.G` and `<name>.B` channels.
+pub fn write_exr<P: AsRef<Path>>(
+    path: P,
+    width: u32,
+    height: u32,
+    layers: &[Layer],
+    pixel_type: ExrPixelType,
+) -> Result<(), OutputError> {
+    let mut channels = SmallVec::new();
+    for layer in layers {
+        assert_eq!(layer.pixels.len(), (width * height) as usize);
+
+        for (axis, channel) in ["R", "G", "B"].iter().enumerate() {
+            let name = if layer.name.is_empty() {
+                channel.to_string()
+            } else {
+                format!("{}.{}", layer.name, channel)
+            };
+            let values = layer.pixels.iter().map(|p| p[axis as u32]);
+            let samples = match pixel_type {
+                ExrPixelType::Half => FlatSamples::F16(values.map(f16::from_f32).collect()),
+                ExrPixelType::Float => FlatSamples::F32(values.collect()),
+            };
+            channels.push(AnyChannel::new(name.as_str(), samples));
+        }
+    }
+
+    let layer = ExrLayer::new(
+        (width as usize, height as usize),
+        LayerAttributes::default(),
+        Encoding::FAST_LOSSLESS,
+        AnyChannels::sort(channels),
+    );
+    Image::from_layer(layer).write().to_file(path)?;
+
+    Ok(())
+}
+
+/// Writes a Radiance RGBE file.
+pub fn write_hdr<P: AsRef<Path>>(
+    path: P,
+    width: u32,
+    height: u32,
+    pixels: &[Vec3],
+) -> Result<(), OutputError> {
+    assert_eq!(pixels.len(), (width * height) as usize);
+
+    let data: Vec<Rgb<f32>> = pixels.iter().map(|p| Rgb([p.x, p.y, p.z])).collect();
+    let file = BufWriter::new(File::create(path)?);
+    HdrEncoder::new(file).encode(&data, width as usize, height as usize)?;
+
+    Ok(())
+}
+
+/// Writes a little-endian colour Portable Float Map.
+pub fn write_pfm<P: AsRef<Path>>(
+    path: P,
+    width: u32,
+    height: u32,
+    pixels: &[Vec3],
+) -> Result<(), OutputError> {
+    assert_eq!(pixels.len(), (width * height) as usize);
+
+    let mut file = BufWriter::new(File::create(path)?);
+    // A negative scale marks little-endian data
+    write!(file, "PF\n{} {}\n-1.0\n", width, height)?;
+
+    // PFM rows go from bottom to top
+    for row in pixels.chunks_exact(width as usize).rev() {
+        for p in row {
+            file.write_all(&p.x.to_le_bytes())?;
+            file.write_all(&p.y.to_le_bytes())?;
+            file.write_all(&p.z.to_le_bytes())?;
+        }
+    }
+    file.flush()?;
+
+    Ok(())
+}
@@ -4,6 +4,8 @@ use crate::vec3::Vec3;
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    /// Instant within the camera shutter the ray was sent at
+    pub time: f32,
 }
 
 impl Ray {
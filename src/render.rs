@@ -1,48 +1,130 @@
-use crate::hit::HitableHandle;
+use crate::hit::{HitRecord, Hitable};
 use crate::ray::Ray;
+use crate::sampler::Sampler;
 use crate::tracer::Tracer;
-use crate::util::local_rng;
 use crate::vec3::Vec3;
 
-use rand::Rng;
-
 impl Tracer {
-    #[allow(clippy::let_and_return)]
-    pub(crate) fn trace(&self, x: u32, y: u32, steps: u32) -> Vec3 {
-        let mut rng = local_rng();
+    /// Returns the sum of `steps` radiance samples through the pixel and the sum
+    /// of their squared luminances, starting with sample number `first_sample`
+    /// so that every pass gets new samples.
+    pub(crate) fn trace(&self, x: u32, y: u32, first_sample: u32, steps: u32) -> (Vec3, f32) {
+        let pixel = y as u64 * self.width as u64 + x as u64;
+        let mut sampler = self.sampler.boxed_clone();
 
         let mut color = Vec3::zero();
+        let mut squares = 0.0;
 
-        for _ in 0..steps {
-            let u = (x as f32 + rng.gen_range(0.0..1.0)) / self.width as f32;
-            let v = (y as f32 + rng.gen_range(0.0..1.0)) / self.height as f32;
+        for sample in first_sample..first_sample + steps {
+            sampler.start_sample(self.seed, pixel, sample);
 
-            let ray = self.camera.get_ray(u, v);
+            let (du, dv) = sampler.get_2d();
+            let u = (x as f32 + du) / self.width as f32;
+            let v = (y as f32 + dv) / self.height as f32;
 
-            color += Tracer::color(ray, &self.world, 0);
-        }
+            let ray = self.camera.get_ray(u, v, sampler.as_mut());
 
-        color /= steps as f32;
-        color = Vec3::new(color.x.sqrt(), color.y.sqrt(), color.z.sqrt());
+            let radiance = self.color(ray, 0, None, sampler.as_mut());
+            color += radiance;
+            squares += radiance.luminance() * radiance.luminance();
+        }
 
-        color
+        (color, squares)
     }
 
-    fn color(ray: Ray, hitable: &HitableHandle, depth: u32) -> Vec3 {
-        match hitable.hit(&ray, 0.001, f32::MAX) {
+    /// Radiance arriving along `ray`. `bsdf_pdf` is the density of the previous
+    /// bounce picking `ray`, if the lights were also sampled directly there.
+    fn color(
+        &self,
+        ray: Ray,
+        depth: u32,
+        bsdf_pdf: Option<f32>,
+        sampler: &mut dyn Sampler,
+    ) -> Vec3 {
+        match self.world.hit(&ray, 0.001, f32::MAX) {
             Some(hit) => {
+                let mut emitted = hit.material.emitted(&ray, &hit);
+                if let Some(bsdf_pdf) = bsdf_pdf {
+                    if emitted != Vec3::zero() {
+                        let light_pdf = self.world.light_pdf(hit.light, ray.origin, ray.direction);
+                        emitted *= power_heuristic(bsdf_pdf, light_pdf);
+                    }
+                }
+
                 if depth >= 50 {
-                    Vec3::zero()
-                } else if let Some((scattered, attenuation)) = hit.material.scatter(&ray, &hit) {
-                    attenuation * Tracer::color(scattered, hitable, depth + 1)
+                    emitted
+                } else if let Some((scattered, attenuation)) =
+                    hit.material.scatter(&ray, &hit, sampler)
+                {
+                    let direct = self.direct_light(&ray, &hit, sampler);
+                    let scattered_pdf = direct
+                        .map(|_| hit.material.pdf(&ray, &hit, scattered.direction))
+                        .filter(|&pdf| pdf > 0.0);
+                    emitted
+                        + direct.unwrap_or_else(Vec3::zero)
+                        + attenuation * self.color(scattered, depth + 1, scattered_pdf, sampler)
                 } else {
-                    Vec3::zero()
+                    emitted
                 }
             }
             None => {
-                let t = 0.5 * (ray.direction.unit().y + 1.0);
-                (1.0 - t) * Vec3::one() + t * Vec3::new(0.5, 0.7, 1.0)
+                let direction = ray.direction.unit();
+                let mut radiance = self.world.environment().radiance(direction);
+                if let Some(bsdf_pdf) = bsdf_pdf {
+                    let environment_pdf = self.world.environment_pdf(direction);
+                    radiance *= power_heuristic(bsdf_pdf, environment_pdf);
+                }
+                radiance
             }
         }
     }
+
+    /// Next event estimation: light reflected towards `ray` from a point sampled
+    /// on one of the lights, weighted against BSDF sampling. `None` if the
+    /// material can't be evaluated, so the lights aren't sampled directly.
+    fn direct_light(&self, ray: &Ray, hit: &HitRecord, sampler: &mut dyn Sampler) -> Option<Vec3> {
+        let sample = match self.world.sample_light(hit.point, sampler) {
+            Some(sample) => sample,
+            // The light that was picked can't be seen from here, BSDF samples
+            // hitting it still have to be weighted
+            None => {
+                return hit
+                    .material
+                    .eval(ray, hit, ray.direction)
+                    .map(|_| Vec3::zero())
+            }
+        };
+        let bsdf = hit.material.eval(ray, hit, sample.direction)?;
+        if bsdf == Vec3::zero() || sample.radiance == Vec3::zero() {
+            return Some(Vec3::zero());
+        }
+
+        let shadow_ray = Ray {
+            origin: hit.point,
+            direction: sample.direction,
+            time: ray.time,
+        };
+        if self
+            .world
+            .hit(&shadow_ray, 0.001, sample.distance * (1.0 - 1e-4))
+            .is_some()
+        {
+            return Some(Vec3::zero());
+        }
+
+        let bsdf_pdf = hit.material.pdf(ray, hit, sample.direction);
+        let weight = power_heuristic(sample.pdf, bsdf_pdf);
+        Some(bsdf * sample.radiance * (weight / sample.pdf))
+    }
+}
+
+/// Weight of a sample taken with density `pdf` against another strategy with density `other_pdf`.
+#[inline]
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
 }
@@ -0,0 +1,360 @@
+use std::str::FromStr;
+
+/// Finalizer of SplitMix64, consecutive inputs give unrelated outputs.
+#[inline]
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Hashes two values into one, used to derive random numbers from counters.
+#[inline]
+pub(crate) fn hash(a: u64, b: u64) -> u64 {
+    mix(a ^ mix(b.wrapping_add(0x9e37_79b9_7f4a_7c15)))
+}
+
+/// Uniform value in `[0, 1)` from the high bits of a hash.
+#[inline]
+fn to_unit(bits: u64) -> f32 {
+    (bits >> 40) as f32 / (1u64 << 24) as f32
+}
+
+/// Source of the numbers in `[0, 1)` that make up one sample of a pixel: the
+/// position in the pixel, on the lens, the time, and then the choices made at
+/// every bounce. Each call uses up the next dimension of the sample.
+///
+/// Low-discrepancy samplers spread the values of every dimension evenly over
+/// the samples of a pixel, which converges faster than independent numbers.
+pub trait Sampler {
+    /// Starts sample `index` of `pixel`. The values only depend on these, the
+    /// seed and the dimension, so renders are reproducible.
+    fn start_sample(&mut self, seed: u64, pixel: u64, index: u32);
+
+    fn get_1d(&mut self) -> f32;
+
+    /// Two dimensions that are stratified together, for sampling a square.
+    fn get_2d(&mut self) -> (f32, f32);
+
+    /// A sampler of the same kind for another thread.
+    fn boxed_clone(&self) -> SamplerHandle;
+}
+
+pub type SamplerHandle = Box<dyn Sampler + Send + Sync>;
+
+/// Independent uniform random numbers, hashed from the sample and the dimension.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct IndependentSampler {
+    state: SampleState,
+}
+
+impl Sampler for IndependentSampler {
+    fn start_sample(&mut self, seed: u64, pixel: u64, index: u32) {
+        self.state.start(seed, pixel, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let key = self.state.next_dimension();
+        to_unit(hash(key, self.state.index as u64))
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        (self.get_1d(), self.get_1d())
+    }
+
+    fn boxed_clone(&self) -> SamplerHandle {
+        Box::new(*self)
+    }
+}
+
+/// Where the current sample is, shared by the samplers that spread the
+/// samples of a pixel over each dimension.
+#[derive(Debug, Default, Copy, Clone)]
+struct SampleState {
+    /// Hash of the seed and the pixel
+    key: u64,
+    index: u32,
+    dimension: u64,
+}
+
+impl SampleState {
+    fn start(&mut self, seed: u64, pixel: u64, index: u32) {
+        self.key = hash(seed, pixel);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    /// Hash of the pixel and the next dimension, the same for all samples.
+    fn next_dimension(&mut self) -> u64 {
+        let key = hash(self.key, self.dimension);
+        self.dimension += 1;
+        key
+    }
+}
+
+/// Jittered strata, shuffled differently for every dimension. Each run of
+/// `samples_per_pixel` samples puts one sample in each stratum.
+#[derive(Debug, Copy, Clone)]
+pub struct StratifiedSampler {
+    samples_per_pixel: u32,
+    state: SampleState,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: u32) -> Self {
+        assert!(samples_per_pixel > 0);
+
+        StratifiedSampler {
+            samples_per_pixel,
+            state: SampleState::default(),
+        }
+    }
+
+    /// The stratum of the current sample among `count` and a random jitter
+    /// for each of its two axes.
+    fn stratum(&mut self, count: u32) -> (u32, f32, f32) {
+        let key = self.state.next_dimension();
+        let n = self.samples_per_pixel;
+        let (round, index) = (self.state.index / n, self.state.index % n);
+
+        let stratum = permute(index, count, hash(key, round as u64) as u32);
+        let jitter = hash(key, (self.state.index as u64) | 1 << 32);
+        (stratum, to_unit(jitter), to_unit(jitter << 24))
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_sample(&mut self, seed: u64, pixel: u64, index: u32) {
+        self.state.start(seed, pixel, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let n = self.samples_per_pixel;
+        let (stratum, jitter, _) = self.stratum(n);
+        ((stratum as f32 + jitter) / n as f32).min(ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        // A grid with at least one cell per sample, as square as possible
+        let n = self.samples_per_pixel;
+        let nx = ((n as f32).sqrt() as u32).max(1);
+        let ny = n.div_ceil(nx);
+        let (cell, jx, jy) = self.stratum(nx * ny);
+        (
+            (((cell % nx) as f32 + jx) / nx as f32).min(ONE_MINUS_EPSILON),
+            (((cell / nx) as f32 + jy) / ny as f32).min(ONE_MINUS_EPSILON),
+        )
+    }
+
+    fn boxed_clone(&self) -> SamplerHandle {
+        Box::new(*self)
+    }
+}
+
+/// Kensler's hashed permutation of `[0, count)`, `index` is mapped to a
+/// different element for every `seed`.
+fn permute(mut i: u32, count: u32, seed: u32) -> u32 {
+    let p = seed;
+    let mut w = count - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    // Cycle walking: repeat until the permutation of the enclosing power of
+    // two lands inside the range
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < count {
+            break;
+        }
+    }
+    i.wrapping_add(p) % count
+}
+
+const PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+/// The Halton sequence with a prime base per dimension, Owen scrambled per
+/// pixel and dimension so that neighbouring pixels don't share their patterns.
+/// Dimensions past the 64th are independent random numbers.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct HaltonSampler {
+    state: SampleState,
+}
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, seed: u64, pixel: u64, index: u32) {
+        self.state.start(seed, pixel, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let dimension = self.state.dimension as usize;
+        let key = self.state.next_dimension();
+        match PRIMES.get(dimension) {
+            Some(&base) => owen_scrambled_radical_inverse(base, self.state.index, key as u32),
+            None => to_unit(hash(key, self.state.index as u64)),
+        }
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        (self.get_1d(), self.get_1d())
+    }
+
+    fn boxed_clone(&self) -> SamplerHandle {
+        Box::new(*self)
+    }
+}
+
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+/// Mirrors the digits of `index` in `base` around the radix point, permuting
+/// every digit based on the ones before it. The leading zeros are permuted
+/// too, down to the precision of an `f32`.
+fn owen_scrambled_radical_inverse(base: u32, mut index: u32, seed: u32) -> f32 {
+    let inv_base = 1.0 / base as f32;
+    let mut inv_base_m = 1.0f32;
+    let mut reversed_digits = 0u64;
+    while 1.0 - inv_base_m < 1.0 {
+        let next = index / base;
+        let digit = index - next * base;
+        let digit_seed = hash(seed as u64, reversed_digits) as u32;
+        reversed_digits = reversed_digits * base as u64 + permute(digit, base, digit_seed) as u64;
+        inv_base_m *= inv_base;
+        index = next;
+    }
+    (reversed_digits as f32 * inv_base_m).min(ONE_MINUS_EPSILON)
+}
+
+/// Owen scrambled Sobol points, using Burley's "Practical Hash-based Owen
+/// Scrambling". Every pair of dimensions is an independently shuffled and
+/// scrambled copy of the first two Sobol dimensions, so there is no limit on
+/// the number of dimensions.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SobolSampler {
+    state: SampleState,
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, seed: u64, pixel: u64, index: u32) {
+        self.state.start(seed, pixel, index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let seed = self.state.next_dimension() as u32;
+        let index = nested_uniform_scramble(self.state.index, seed);
+        let x = nested_uniform_scramble(index.reverse_bits(), hash_u32(seed, 0));
+        sobol_to_unit(x)
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let seed = self.state.next_dimension() as u32;
+        let index = nested_uniform_scramble(self.state.index, seed);
+        let x = nested_uniform_scramble(index.reverse_bits(), hash_u32(seed, 0));
+        let y = nested_uniform_scramble(sobol_second_dimension(index), hash_u32(seed, 1));
+        (sobol_to_unit(x), sobol_to_unit(y))
+    }
+
+    fn boxed_clone(&self) -> SamplerHandle {
+        Box::new(*self)
+    }
+}
+
+#[inline]
+fn hash_u32(seed: u32, value: u64) -> u32 {
+    hash(seed as u64, value) as u32
+}
+
+#[inline]
+fn sobol_to_unit(x: u32) -> f32 {
+    (x >> 8) as f32 / (1u32 << 24) as f32
+}
+
+/// The second dimension of the Sobol sequence, the first one is the bit reversed index.
+fn sobol_second_dimension(mut index: u32) -> u32 {
+    let mut direction = 1u32 << 31;
+    let mut result = 0;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+    result
+}
+
+/// Laine and Karras' hash that only lets bits affect higher bits.
+#[inline]
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+/// Owen scrambling, every bit is flipped based on the bits above it.
+#[inline]
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+/// The samplers that can be picked by name, e.g. on the command line.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum SamplerKind {
+    Independent,
+    Stratified,
+    Halton,
+    #[default]
+    Sobol,
+}
+
+impl SamplerKind {
+    /// `samples_per_pixel` is the number of samples the stratified sampler
+    /// spreads over its strata.
+    pub fn create(self, samples_per_pixel: u32) -> SamplerHandle {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::default()),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(samples_per_pixel)),
+            SamplerKind::Halton => Box::new(HaltonSampler::default()),
+            SamplerKind::Sobol => Box::new(SobolSampler::default()),
+        }
+    }
+}
+
+impl FromStr for SamplerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "independent" => Ok(SamplerKind::Independent),
+            "stratified" => Ok(SamplerKind::Stratified),
+            "halton" => Ok(SamplerKind::Halton),
+            "sobol" => Ok(SamplerKind::Sobol),
+            _ => Err(format!("unknown sampler `{}`", s)),
+        }
+    }
+}
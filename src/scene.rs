@@ -0,0 +1,1168 @@
+use crate::bvh::BvhOptions;
+use crate::camera::Camera;
+use crate::environment::{
+    ConstantEnvironment, EnvironmentError, EnvironmentHandle, EnvironmentMap, GradientEnvironment,
+};
+use crate::hit::{HitableHandle, MovingSphere, Plane, Quad, Sphere, Triangle, TriangleMesh};
+use crate::instance::{AnimatedInstance, Instance};
+use crate::material::{Dielectric, DiffuseLight, Lambertian, MaterialHandle, Metal};
+use crate::obj::{self, ObjError};
+use crate::sampler::SamplerKind;
+use crate::texture::{
+    CheckerTexture, ConstantTexture, ImageTexture, NoiseTexture, Perlin, TextureHandle, WrapMode,
+};
+use crate::tonemap::{ToneMap, ToneMapOperator, MAX_WHITE_BALANCE, MIN_WHITE_BALANCE};
+use crate::tracer::AdaptiveOptions;
+use crate::transform::{AnimatedTransform, Quat, Trs};
+use crate::vec3::Vec3;
+use crate::world::World;
+
+use image::ImageError;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const WIDTH: u32 = 1280;
+const HEIGHT: u32 = 720;
+const BLOCK_SIZE: u32 = 40;
+const SAMPLES: u32 = 1000;
+
+/// Everything needed to set up a `Tracer` and save its output.
+pub struct Scene {
+    pub width: u32,
+    pub height: u32,
+    pub block_size: u32,
+    pub samples: u32,
+    pub output: PathBuf,
+    /// Seed of the random numbers used while rendering
+    pub seed: u64,
+    pub sampler: SamplerKind,
+    /// Keep sampling noisy blocks after `samples` if set
+    pub adaptive: Option<AdaptiveOptions>,
+    pub tone_map: ToneMap,
+    pub camera: Camera,
+    pub world: World,
+}
+
+/// Values that replace the ones in a scene description, e.g. from the command line.
+#[derive(Debug, Default, Clone)]
+pub struct Overrides {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub block_size: Option<u32>,
+    pub samples: Option<u32>,
+    pub output: Option<PathBuf>,
+    pub seed: Option<u64>,
+    pub sampler: Option<SamplerKind>,
+    /// Turns on adaptive sampling
+    pub adaptive_threshold: Option<f32>,
+    pub max_samples: Option<u32>,
+    /// In stops
+    pub exposure: Option<f32>,
+    /// In Kelvin
+    pub white_balance: Option<f32>,
+    pub tone_map: Option<ToneMapOperator>,
+    pub white_point: Option<f32>,
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Syntax {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    Invalid {
+        path: PathBuf,
+        entry: String,
+        message: String,
+    },
+    Mesh {
+        path: PathBuf,
+        entry: String,
+        source: ObjError,
+    },
+    Texture {
+        path: PathBuf,
+        entry: String,
+        source: Box<ImageError>,
+    },
+    Environment {
+        path: PathBuf,
+        source: Box<EnvironmentError>,
+    },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            SceneError::Syntax { path, source } => write!(f, "{}: {}", path.display(), source),
+            SceneError::Invalid {
+                path,
+                entry,
+                message,
+            } => write!(f, "{}: `{}`: {}", path.display(), entry, message),
+            SceneError::Mesh {
+                path,
+                entry,
+                source,
+            } => write!(f, "{}: `{}`: {}", path.display(), entry, source),
+            SceneError::Texture {
+                path,
+                entry,
+                source,
+            } => write!(f, "{}: `{}`: {}", path.display(), entry, source),
+            SceneError::Environment { path, source } => {
+                write!(f, "{}: `environment`: {}", path.display(), source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SceneError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SceneError::Io { source, .. } => Some(source),
+            SceneError::Syntax { source, .. } => Some(source),
+            SceneError::Mesh { source, .. } => Some(source),
+            SceneError::Texture { source, .. } => Some(source),
+            SceneError::Environment { source, .. } => Some(source),
+            SceneError::Invalid { .. } => None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDesc {
+    width: u32,
+    height: u32,
+    #[serde(default = "default_block_size")]
+    block_size: u32,
+    samples: u32,
+    #[serde(default = "default_output")]
+    output: PathBuf,
+    #[serde(default)]
+    seed: u64,
+    #[serde(default)]
+    sampler: SamplerDesc,
+    #[serde(default)]
+    adaptive: Option<AdaptiveDesc>,
+    #[serde(default)]
+    tone_map: ToneMapDesc,
+    #[serde(default)]
+    bvh: BvhDesc,
+    camera: CameraDesc,
+    /// The sky gradient if missing
+    #[serde(default)]
+    environment: Option<EnvironmentDesc>,
+    #[serde(default)]
+    textures: BTreeMap<String, TextureDesc>,
+    #[serde(default)]
+    materials: BTreeMap<String, MaterialDesc>,
+    primitives: Vec<PrimitiveDesc>,
+}
+
+fn default_block_size() -> u32 {
+    BLOCK_SIZE
+}
+
+fn default_output() -> PathBuf {
+    PathBuf::from("renders/render.png")
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+enum SamplerDesc {
+    Independent,
+    Stratified,
+    Halton,
+    #[default]
+    Sobol,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AdaptiveDesc {
+    threshold: f32,
+    /// 8 times `samples` if missing
+    #[serde(default)]
+    max_samples: Option<u32>,
+}
+
+/// `BvhOptions::default()` for the missing fields
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct BvhDesc {
+    #[serde(default)]
+    max_leaf_size: Option<usize>,
+    #[serde(default)]
+    bins: Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ToneMapDesc {
+    #[serde(default)]
+    operator: ToneMapOperatorDesc,
+    /// In stops
+    #[serde(default)]
+    exposure: f32,
+    /// Colour temperature of the light that should look white, in Kelvin
+    #[serde(default)]
+    white_balance: Option<f32>,
+    /// Luminance that `reinhard_extended` maps to white
+    #[serde(default = "default_white_point")]
+    white_point: f32,
+}
+
+impl Default for ToneMapDesc {
+    fn default() -> Self {
+        ToneMapDesc {
+            operator: ToneMapOperatorDesc::default(),
+            exposure: 0.0,
+            white_balance: None,
+            white_point: default_white_point(),
+        }
+    }
+}
+
+fn default_white_point() -> f32 {
+    ToneMap::default().white_point()
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+enum ToneMapOperatorDesc {
+    #[default]
+    Clamp,
+    Reinhard,
+    ReinhardExtended,
+    Aces,
+    Agx,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    look_from: [f32; 3],
+    look_at: [f32; 3],
+    #[serde(default = "default_vup")]
+    vup: [f32; 3],
+    v_fov: f32,
+    aspect_ratio: Option<f32>,
+    focus_dist: f32,
+    #[serde(default)]
+    aperture: f32,
+    /// Open and close time of the shutter, for motion blur
+    #[serde(default)]
+    shutter: [f32; 2],
+}
+
+fn default_vup() -> [f32; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum EnvironmentDesc {
+    Constant {
+        color: [f32; 3],
+    },
+    /// Blends from `bottom` straight down to `top` straight up
+    Gradient {
+        #[serde(default = "default_gradient_bottom")]
+        bottom: [f32; 3],
+        #[serde(default = "default_gradient_top")]
+        top: [f32; 3],
+    },
+    /// Equirectangular `.hdr` or `.exr` file relative to the scene file,
+    /// turned by `rotation` degrees around the y axis
+    Map {
+        path: PathBuf,
+        #[serde(default)]
+        rotation: f32,
+        #[serde(default = "default_intensity")]
+        intensity: f32,
+    },
+}
+
+fn default_gradient_bottom() -> [f32; 3] {
+    [1.0; 3]
+}
+
+fn default_gradient_top() -> [f32; 3] {
+    [0.5, 0.7, 1.0]
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDesc {
+    Lambertian {
+        albedo: ColorDesc,
+    },
+    Metal {
+        albedo: ColorDesc,
+        fuzz: f32,
+    },
+    Dielectric {
+        refraction_idx: f32,
+    },
+    DiffuseLight {
+        color: [f32; 3],
+        #[serde(default = "default_intensity")]
+        intensity: f32,
+        #[serde(default)]
+        one_sided: bool,
+    },
+}
+
+fn default_intensity() -> f32 {
+    1.0
+}
+
+/// Either an RGB colour or the name of an entry in `textures`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColorDesc {
+    Rgb([f32; 3]),
+    Texture(String),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum TextureDesc {
+    Constant {
+        color: [f32; 3],
+    },
+    /// Solid checkerboard of cubes with sides of `scale`
+    Checker {
+        odd: [f32; 3],
+        even: [f32; 3],
+        #[serde(default = "default_texture_scale")]
+        scale: f32,
+    },
+    /// Image file relative to the scene file
+    Image {
+        path: PathBuf,
+        #[serde(default)]
+        wrap: WrapDesc,
+    },
+    /// Marble-like Perlin noise
+    Noise {
+        #[serde(default = "default_noise_color")]
+        color: [f32; 3],
+        #[serde(default = "default_texture_scale")]
+        scale: f32,
+        #[serde(default)]
+        seed: u64,
+    },
+}
+
+fn default_texture_scale() -> f32 {
+    1.0
+}
+
+fn default_noise_color() -> [f32; 3] {
+    [1.0; 3]
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+enum WrapDesc {
+    #[default]
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum PrimitiveDesc {
+    Sphere {
+        center: [f32; 3],
+        radius: f32,
+        material: String,
+    },
+    /// Sphere moving from `center0` at `time0` to `center1` at `time1`
+    MovingSphere {
+        center0: [f32; 3],
+        center1: [f32; 3],
+        #[serde(default)]
+        time0: f32,
+        #[serde(default = "default_time1")]
+        time1: f32,
+        radius: f32,
+        material: String,
+    },
+    Triangle {
+        vertices: [[f32; 3]; 3],
+        material: String,
+    },
+    Quad {
+        corner: [f32; 3],
+        u: [f32; 3],
+        v: [f32; 3],
+        material: String,
+    },
+    /// Infinite plane through `point`
+    Plane {
+        point: [f32; 3],
+        normal: [f32; 3],
+        material: String,
+    },
+    /// Wavefront OBJ file, relative to the scene file, using its own MTL materials
+    Mesh { path: PathBuf },
+    /// A transformed copy of an OBJ file, all instances of a file share its BVH
+    Instance {
+        path: PathBuf,
+        #[serde(default)]
+        transform: TransformDesc,
+        #[serde(default)]
+        motion: Option<MotionDesc>,
+    },
+}
+
+fn default_time1() -> f32 {
+    1.0
+}
+
+/// Moves an instance from its `transform` at `time0` to this `transform` at `time1`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MotionDesc {
+    transform: TransformDesc,
+    #[serde(default)]
+    time0: f32,
+    #[serde(default = "default_time1")]
+    time1: f32,
+}
+
+/// Scale, then rotation, then translation.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TransformDesc {
+    #[serde(default)]
+    translate: [f32; 3],
+    #[serde(default)]
+    rotate: Option<RotationDesc>,
+    #[serde(default = "default_scale")]
+    scale: [f32; 3],
+}
+
+impl Default for TransformDesc {
+    fn default() -> Self {
+        TransformDesc {
+            translate: [0.0; 3],
+            rotate: None,
+            scale: default_scale(),
+        }
+    }
+}
+
+fn default_scale() -> [f32; 3] {
+    [1.0; 3]
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RotationDesc {
+    axis: [f32; 3],
+    degrees: f32,
+}
+
+/// Reads a JSON scene description, see `scenes/` for examples.
+pub fn load_scene<P: AsRef<Path>>(path: P) -> Result<Scene, SceneError> {
+    load_scene_with(path, &Overrides::default())
+}
+
+pub fn load_scene_with<P: AsRef<Path>>(
+    path: P,
+    overrides: &Overrides,
+) -> Result<Scene, SceneError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|source| SceneError::Io {
+        path: path.to_owned(),
+        source,
+    })?;
+    let mut desc: SceneDesc =
+        serde_json::from_str(&source).map_err(|source| SceneError::Syntax {
+            path: path.to_owned(),
+            source,
+        })?;
+
+    desc.width = overrides.width.unwrap_or(desc.width);
+    desc.height = overrides.height.unwrap_or(desc.height);
+    desc.block_size = overrides.block_size.unwrap_or(desc.block_size);
+    desc.samples = overrides.samples.unwrap_or(desc.samples);
+    desc.seed = overrides.seed.unwrap_or(desc.seed);
+    if let Some(output) = &overrides.output {
+        desc.output = output.clone();
+    }
+    if let Some(threshold) = overrides.adaptive_threshold {
+        let max_samples = desc.adaptive.and_then(|adaptive| adaptive.max_samples);
+        desc.adaptive = Some(AdaptiveDesc {
+            threshold,
+            max_samples,
+        });
+    }
+    if let Some(adaptive) = &mut desc.adaptive {
+        adaptive.max_samples = overrides.max_samples.or(adaptive.max_samples);
+    }
+
+    let mut scene = build_scene(desc, path)?;
+    scene.sampler = overrides.sampler.unwrap_or(scene.sampler);
+    scene.tone_map = override_tone_map(scene.tone_map, overrides);
+    Ok(scene)
+}
+
+fn override_tone_map(mut tone_map: ToneMap, overrides: &Overrides) -> ToneMap {
+    if let Some(exposure) = overrides.exposure {
+        tone_map = tone_map.with_exposure(exposure);
+    }
+    if let Some(kelvin) = overrides.white_balance {
+        tone_map = tone_map.with_white_balance(kelvin);
+    }
+    if let Some(operator) = overrides.tone_map {
+        tone_map = tone_map.with_operator(operator);
+    }
+    if let Some(white_point) = overrides.white_point {
+        tone_map = tone_map.with_white_point(white_point);
+    }
+    tone_map
+}
+
+fn build_scene(desc: SceneDesc, path: &Path) -> Result<Scene, SceneError> {
+    let invalid = |entry: &str, message: &str| SceneError::Invalid {
+        path: path.to_owned(),
+        entry: entry.to_owned(),
+        message: message.to_owned(),
+    };
+
+    // serde_json never produces NaN, so plain comparisons are enough below
+    if desc.width == 0 || desc.height == 0 {
+        return Err(invalid("width", "resolution must not be zero"));
+    }
+    if desc.block_size == 0 {
+        return Err(invalid("block_size", "must not be zero"));
+    }
+    if !desc.width.is_multiple_of(desc.block_size) || !desc.height.is_multiple_of(desc.block_size) {
+        return Err(invalid(
+            "block_size",
+            "must divide both the width and the height",
+        ));
+    }
+    if desc.samples == 0 {
+        return Err(invalid("samples", "must not be zero"));
+    }
+    let adaptive = match &desc.adaptive {
+        Some(adaptive) => {
+            if adaptive.threshold <= 0.0 {
+                return Err(invalid("adaptive.threshold", "must be positive"));
+            }
+            let max_samples = adaptive
+                .max_samples
+                .unwrap_or_else(|| desc.samples.saturating_mul(8));
+            if max_samples < desc.samples {
+                return Err(invalid(
+                    "adaptive.max_samples",
+                    "must not be less than `samples`",
+                ));
+            }
+            Some(AdaptiveOptions {
+                threshold: adaptive.threshold,
+                max_samples,
+            })
+        }
+        None => None,
+    };
+    let default_bvh = BvhOptions::default();
+    let bvh_options = BvhOptions {
+        max_leaf_size: desc.bvh.max_leaf_size.unwrap_or(default_bvh.max_leaf_size),
+        bins: desc.bvh.bins.unwrap_or(default_bvh.bins),
+    };
+    if bvh_options.max_leaf_size == 0 || bvh_options.max_leaf_size > u16::MAX as usize {
+        return Err(invalid("bvh.max_leaf_size", "must be between 1 and 65535"));
+    }
+    if bvh_options.bins < 2 {
+        return Err(invalid("bvh.bins", "must be at least 2"));
+    }
+    let tone_map = build_tone_map(&desc.tone_map)
+        .map_err(|(entry, message)| invalid(&format!("tone_map.{}", entry), message))?;
+
+    let camera = build_camera(&desc.camera, desc.width as f32 / desc.height as f32)
+        .map_err(|(entry, message)| invalid(&format!("camera.{}", entry), message))?;
+
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut textures = HashMap::new();
+    for (name, texture) in &desc.textures {
+        let entry = format!("textures.{}", name);
+        let texture = build_texture(texture, base_dir).map_err(|error| match error {
+            TextureError::Invalid(message) => invalid(&entry, message),
+            TextureError::Image(source) => SceneError::Texture {
+                path: path.to_owned(),
+                entry: entry.clone(),
+                source: Box::new(source),
+            },
+        })?;
+        textures.insert(name.as_str(), texture);
+    }
+
+    let mut materials = HashMap::new();
+    for (name, material) in &desc.materials {
+        let material = build_material(material, &textures)
+            .map_err(|message| invalid(&format!("materials.{}", name), &message))?;
+        materials.insert(name.as_str(), material);
+    }
+
+    let mut world: Vec<Arc<HitableHandle>> = Vec::with_capacity(desc.primitives.len());
+    let mut instanced_meshes: HashMap<PathBuf, Arc<HitableHandle>> = HashMap::new();
+    for (idx, primitive) in desc.primitives.iter().enumerate() {
+        let entry = format!("primitives[{}]", idx);
+        let material = |name: &str| {
+            materials
+                .get(name)
+                .cloned()
+                .ok_or_else(|| invalid(&entry, &format!("unknown material `{}`", name)))
+        };
+
+        match primitive {
+            PrimitiveDesc::Sphere {
+                center,
+                radius,
+                material: material_name,
+            } => {
+                if *radius <= 0.0 {
+                    return Err(invalid(&entry, "radius must be positive"));
+                }
+                world.push(Arc::new(Box::new(Sphere {
+                    center: vec3(*center),
+                    radius: *radius,
+                    material: material(material_name)?,
+                })));
+            }
+            PrimitiveDesc::MovingSphere {
+                center0,
+                center1,
+                time0,
+                time1,
+                radius,
+                material: material_name,
+            } => {
+                if *radius <= 0.0 {
+                    return Err(invalid(&entry, "radius must be positive"));
+                }
+                if time1 < time0 {
+                    return Err(invalid(&entry, "time1 must not be before time0"));
+                }
+                world.push(Arc::new(Box::new(MovingSphere {
+                    center0: vec3(*center0),
+                    center1: vec3(*center1),
+                    time0: *time0,
+                    time1: *time1,
+                    radius: *radius,
+                    material: material(material_name)?,
+                })));
+            }
+            PrimitiveDesc::Triangle {
+                vertices,
+                material: material_name,
+            } => {
+                world.push(Arc::new(Box::new(Triangle {
+                    vertices: [vec3(vertices[0]), vec3(vertices[1]), vec3(vertices[2])],
+                    normals: None,
+                    material: material(material_name)?,
+                })));
+            }
+            PrimitiveDesc::Quad {
+                corner,
+                u,
+                v,
+                material: material_name,
+            } => {
+                if Vec3::cross(vec3(*u), vec3(*v)).squared_length() == 0.0 {
+                    return Err(invalid(&entry, "u and v must span a parallelogram"));
+                }
+                world.push(Arc::new(Box::new(Quad {
+                    corner: vec3(*corner),
+                    u: vec3(*u),
+                    v: vec3(*v),
+                    material: material(material_name)?,
+                })));
+            }
+            PrimitiveDesc::Plane {
+                point,
+                normal,
+                material: material_name,
+            } => {
+                if vec3(*normal).squared_length() == 0.0 {
+                    return Err(invalid(&entry, "normal must not be zero"));
+                }
+                world.push(Arc::new(Box::new(Plane {
+                    point: vec3(*point),
+                    normal: vec3(*normal),
+                    material: material(material_name)?,
+                })));
+            }
+            PrimitiveDesc::Mesh { path: mesh_path } => {
+                // The faces go into the scene BVH so that emissive ones become lights
+                let mesh_error = |source| SceneError::Mesh {
+                    path: path.to_owned(),
+                    entry: entry.clone(),
+                    source,
+                };
+                let mesh_path = base_dir.join(mesh_path);
+                let model = obj::load_obj_model(&mesh_path).map_err(mesh_error)?;
+                if model.mesh.faces.is_empty() {
+                    return Err(mesh_error(ObjError::Empty { path: mesh_path }));
+                }
+                world.extend(TriangleMesh::triangles(&Arc::new(model.mesh)));
+            }
+            PrimitiveDesc::Instance {
+                path: mesh_path,
+                transform,
+                motion,
+            } => {
+                let mesh_path = base_dir.join(mesh_path);
+                let mesh = match instanced_meshes.get(&mesh_path) {
+                    Some(mesh) => mesh.clone(),
+                    None => {
+                        let mesh: Arc<HitableHandle> =
+                            Arc::new(obj::load_obj(&mesh_path).map_err(|source| {
+                                SceneError::Mesh {
+                                    path: path.to_owned(),
+                                    entry: entry.clone(),
+                                    source,
+                                }
+                            })?);
+                        instanced_meshes.insert(mesh_path, mesh.clone());
+                        mesh
+                    }
+                };
+                let start = build_trs(transform)
+                    .map_err(|message| invalid(&format!("{}.transform", entry), message))?;
+                match motion {
+                    Some(motion) => {
+                        let end = build_trs(&motion.transform).map_err(|message| {
+                            invalid(&format!("{}.motion.transform", entry), message)
+                        })?;
+                        if motion.time1 < motion.time0 {
+                            return Err(invalid(
+                                &format!("{}.motion", entry),
+                                "time1 must not be before time0",
+                            ));
+                        }
+                        let transform = AnimatedTransform {
+                            start,
+                            end,
+                            time0: motion.time0,
+                            time1: motion.time1,
+                        };
+                        world.push(Arc::new(Box::new(AnimatedInstance::new(mesh, transform))));
+                    }
+                    None => {
+                        world.push(Arc::new(Box::new(Instance::new(
+                            mesh,
+                            start.to_transform(),
+                        ))));
+                    }
+                }
+            }
+        }
+    }
+
+    if world.is_empty() {
+        return Err(invalid("primitives", "scene is empty"));
+    }
+    let mut world = World::with_options(world, camera.time0, camera.time1, &bvh_options);
+    if let Some(environment) = &desc.environment {
+        let environment =
+            build_environment(environment, base_dir).map_err(|error| match error {
+                BuildEnvironmentError::Invalid(message) => invalid("environment", message),
+                BuildEnvironmentError::Map(source) => SceneError::Environment {
+                    path: path.to_owned(),
+                    source: Box::new(source),
+                },
+            })?;
+        world = world.with_environment(environment);
+    }
+
+    Ok(Scene {
+        width: desc.width,
+        height: desc.height,
+        block_size: desc.block_size,
+        samples: desc.samples,
+        output: desc.output,
+        seed: desc.seed,
+        sampler: match desc.sampler {
+            SamplerDesc::Independent => SamplerKind::Independent,
+            SamplerDesc::Stratified => SamplerKind::Stratified,
+            SamplerDesc::Halton => SamplerKind::Halton,
+            SamplerDesc::Sobol => SamplerKind::Sobol,
+        },
+        adaptive,
+        tone_map,
+        camera,
+        world,
+    })
+}
+
+fn build_tone_map(desc: &ToneMapDesc) -> Result<ToneMap, (&'static str, &'static str)> {
+    if !desc.exposure.is_finite() {
+        return Err(("exposure", "must be finite"));
+    }
+    if desc.white_point <= 0.0 {
+        return Err(("white_point", "must be positive"));
+    }
+
+    let mut tone_map = ToneMap::default()
+        .with_exposure(desc.exposure)
+        .with_white_point(desc.white_point)
+        .with_operator(match desc.operator {
+            ToneMapOperatorDesc::Clamp => ToneMapOperator::Clamp,
+            ToneMapOperatorDesc::Reinhard => ToneMapOperator::Reinhard,
+            ToneMapOperatorDesc::ReinhardExtended => ToneMapOperator::ReinhardExtended,
+            ToneMapOperatorDesc::Aces => ToneMapOperator::Aces,
+            ToneMapOperatorDesc::Agx => ToneMapOperator::Agx,
+        });
+    if let Some(kelvin) = desc.white_balance {
+        if !(MIN_WHITE_BALANCE..=MAX_WHITE_BALANCE).contains(&kelvin) {
+            return Err(("white_balance", "must be between 1667 and 25000 Kelvin"));
+        }
+        tone_map = tone_map.with_white_balance(kelvin);
+    }
+    Ok(tone_map)
+}
+
+fn build_camera(
+    desc: &CameraDesc,
+    image_aspect_ratio: f32,
+) -> Result<Camera, (&'static str, &'static str)> {
+    let look_from = vec3(desc.look_from);
+    let look_at = vec3(desc.look_at);
+    let vup = vec3(desc.vup);
+
+    if look_from == look_at {
+        return Err(("look_at", "must differ from `look_from`"));
+    }
+    if Vec3::cross(vup, look_from - look_at).squared_length() == 0.0 {
+        return Err(("vup", "must not be parallel to the view direction"));
+    }
+    if !(desc.v_fov > 0.0 && desc.v_fov < 180.0) {
+        return Err(("v_fov", "must be between 0 and 180 degrees"));
+    }
+    let aspect_ratio = desc.aspect_ratio.unwrap_or(image_aspect_ratio);
+    if aspect_ratio <= 0.0 {
+        return Err(("aspect_ratio", "must be positive"));
+    }
+    if desc.focus_dist <= 0.0 {
+        return Err(("focus_dist", "must be positive"));
+    }
+    if desc.aperture < 0.0 {
+        return Err(("aperture", "must not be negative"));
+    }
+    if desc.shutter[1] < desc.shutter[0] {
+        return Err(("shutter", "must not close before it opens"));
+    }
+
+    Ok(Camera::new(
+        look_from,
+        look_at,
+        vup,
+        desc.v_fov,
+        aspect_ratio,
+        desc.focus_dist,
+        desc.aperture,
+    )
+    .with_shutter(desc.shutter[0], desc.shutter[1]))
+}
+
+enum BuildEnvironmentError {
+    Invalid(&'static str),
+    Map(EnvironmentError),
+}
+
+fn build_environment(
+    desc: &EnvironmentDesc,
+    base_dir: &Path,
+) -> Result<EnvironmentHandle, BuildEnvironmentError> {
+    let environment: EnvironmentHandle = match desc {
+        EnvironmentDesc::Constant { color } => Box::new(ConstantEnvironment {
+            color: vec3(*color),
+        }),
+        EnvironmentDesc::Gradient { bottom, top } => Box::new(GradientEnvironment {
+            bottom: vec3(*bottom),
+            top: vec3(*top),
+        }),
+        EnvironmentDesc::Map {
+            path,
+            rotation,
+            intensity,
+        } => {
+            if *intensity < 0.0 {
+                return Err(BuildEnvironmentError::Invalid(
+                    "intensity must not be negative",
+                ));
+            }
+            let map =
+                EnvironmentMap::open(base_dir.join(path)).map_err(BuildEnvironmentError::Map)?;
+            Box::new(
+                map.with_rotation(rotation.to_radians())
+                    .with_intensity(*intensity),
+            )
+        }
+    };
+    Ok(environment)
+}
+
+enum TextureError {
+    Invalid(&'static str),
+    Image(ImageError),
+}
+
+fn build_texture(desc: &TextureDesc, base_dir: &Path) -> Result<Arc<TextureHandle>, TextureError> {
+    let texture: TextureHandle = match *desc {
+        TextureDesc::Constant { color } => Box::new(ConstantTexture { color: vec3(color) }),
+        TextureDesc::Checker { odd, even, scale } => {
+            if scale <= 0.0 {
+                return Err(TextureError::Invalid("scale must be positive"));
+            }
+            Box::new(CheckerTexture {
+                odd: ConstantTexture::handle(vec3(odd)),
+                even: ConstantTexture::handle(vec3(even)),
+                scale,
+            })
+        }
+        TextureDesc::Image { ref path, ref wrap } => {
+            let wrap = match wrap {
+                WrapDesc::Repeat => WrapMode::Repeat,
+                WrapDesc::Clamp => WrapMode::Clamp,
+                WrapDesc::Mirror => WrapMode::Mirror,
+            };
+            Box::new(ImageTexture::open(base_dir.join(path), wrap).map_err(TextureError::Image)?)
+        }
+        TextureDesc::Noise { color, scale, seed } => Box::new(NoiseTexture {
+            perlin: Perlin::new(seed),
+            color: vec3(color),
+            scale,
+        }),
+    };
+
+    Ok(Arc::new(texture))
+}
+
+fn build_color(
+    desc: &ColorDesc,
+    textures: &HashMap<&str, Arc<TextureHandle>>,
+) -> Result<Arc<TextureHandle>, String> {
+    match desc {
+        ColorDesc::Rgb(color) => Ok(ConstantTexture::handle(vec3(*color))),
+        ColorDesc::Texture(name) => textures
+            .get(name.as_str())
+            .cloned()
+            .ok_or_else(|| format!("unknown texture `{}`", name)),
+    }
+}
+
+fn build_material(
+    desc: &MaterialDesc,
+    textures: &HashMap<&str, Arc<TextureHandle>>,
+) -> Result<Arc<MaterialHandle>, String> {
+    let material: MaterialHandle = match desc {
+        MaterialDesc::Lambertian { albedo } => Box::new(Lambertian {
+            albedo: build_color(albedo, textures)?,
+        }),
+        MaterialDesc::Metal { albedo, fuzz } => {
+            if !(0.0..=1.0).contains(fuzz) {
+                return Err("fuzz must be between 0 and 1".to_owned());
+            }
+            Box::new(Metal {
+                albedo: build_color(albedo, textures)?,
+                fuzz: *fuzz,
+            })
+        }
+        MaterialDesc::Dielectric { refraction_idx } => {
+            if *refraction_idx <= 0.0 {
+                return Err("refraction_idx must be positive".to_owned());
+            }
+            Box::new(Dielectric {
+                refraction_idx: *refraction_idx,
+            })
+        }
+        MaterialDesc::DiffuseLight {
+            color,
+            intensity,
+            one_sided,
+        } => {
+            if *intensity < 0.0 {
+                return Err("intensity must not be negative".to_owned());
+            }
+            Box::new(DiffuseLight {
+                color: vec3(*color),
+                intensity: *intensity,
+                one_sided: *one_sided,
+            })
+        }
+    };
+
+    Ok(Arc::new(material))
+}
+
+fn build_trs(desc: &TransformDesc) -> Result<Trs, &'static str> {
+    if desc.scale.contains(&0.0) {
+        return Err("scale must not be zero");
+    }
+    let rotation = match &desc.rotate {
+        Some(rotation) => {
+            if vec3(rotation.axis).squared_length() == 0.0 {
+                return Err("rotation axis must not be zero");
+            }
+            Quat::from_axis_angle(vec3(rotation.axis), rotation.degrees.to_radians())
+        }
+        None => Quat::identity(),
+    };
+
+    Ok(Trs {
+        translation: vec3(desc.translate),
+        rotation,
+        scale: vec3(desc.scale),
+    })
+}
+
+#[inline]
+fn vec3(v: [f32; 3]) -> Vec3 {
+    Vec3::new(v[0], v[1], v[2])
+}
+
+/// The random spheres scene, at 1280x720 unless overridden.
+pub fn default_scene(overrides: &Overrides) -> Scene {
+    let width = overrides.width.unwrap_or(WIDTH);
+    let height = overrides.height.unwrap_or(HEIGHT);
+    let seed = overrides.seed.unwrap_or(0);
+    let samples = overrides.samples.unwrap_or(SAMPLES);
+
+    let look_from = Vec3::new(13.0, 2.0, 3.0);
+    let look_at = Vec3::new(0.0, 0.0, 0.0);
+    let aspect_ratio = width as f32 / height as f32;
+    let focus_dist = 10.0;
+    let aperture = 0.1;
+    let camera = Camera::new(
+        look_from,
+        look_at,
+        Vec3::new(0.0, 1.0, 0.0),
+        20.0,
+        aspect_ratio,
+        focus_dist,
+        aperture,
+    );
+
+    Scene {
+        width,
+        height,
+        block_size: overrides.block_size.unwrap_or(BLOCK_SIZE),
+        samples,
+        output: overrides.output.clone().unwrap_or_else(default_output),
+        seed,
+        sampler: overrides.sampler.unwrap_or_default(),
+        adaptive: overrides
+            .adaptive_threshold
+            .map(|threshold| AdaptiveOptions {
+                threshold,
+                max_samples: overrides
+                    .max_samples
+                    .unwrap_or_else(|| samples.saturating_mul(8))
+                    .max(samples),
+            }),
+        tone_map: override_tone_map(ToneMap::default(), overrides),
+        camera,
+        world: random_scene(seed),
+    }
+}
+
+/// The same seed always places the same spheres.
+pub fn random_scene(seed: u64) -> World {
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let mut world: Vec<Arc<HitableHandle>> = Vec::with_capacity(500);
+
+    world.push(Arc::new(Box::new(Sphere {
+        center: Vec3::new(0.0, -1000.0, -1.0),
+        radius: 1000.0,
+        material: Arc::new(Box::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)))),
+    })));
+
+    for a in -11..11 {
+        for b in -11..11 {
+            let choose_mat = rng.gen_range(0.0..1.0);
+            let center = Vec3::new(
+                a as f32 + 0.9 * rng.gen_range(0.0..1.0),
+                0.2,
+                b as f32 + 0.9 * rng.gen_range(0.0..1.0),
+            );
+
+            if (center - Vec3::new(4.0, 0.2, 0.0)).length() > 0.9 {
+                let material: Arc<MaterialHandle>;
+
+                if choose_mat < 0.8 {
+                    // diffuse
+                    material = Arc::new(Box::new(Lambertian::new(Vec3::new(
+                        rng.gen_range(0.0..1.0) * rng.gen_range(0.0..1.0),
+                        rng.gen_range(0.0..1.0) * rng.gen_range(0.0..1.0),
+                        rng.gen_range(0.0..1.0) * rng.gen_range(0.0..1.0),
+                    ))));
+                } else if choose_mat < 0.95 {
+                    // metal
+                    material = Arc::new(Box::new(Metal::new(
+                        Vec3::new(
+                            0.5 * (1.0 + rng.gen_range(0.0..1.0)),
+                            0.5 * (1.0 + rng.gen_range(0.0..1.0)),
+                            0.5 * (1.0 + rng.gen_range(0.0..1.0)),
+                        ),
+                        0.5 * rng.gen_range(0.0..1.0),
+                    )));
+                } else {
+                    // glass
+                    material = Arc::new(Box::new(Dielectric {
+                        refraction_idx: 1.5,
+                    }));
+                }
+
+                world.push(Arc::new(Box::new(Sphere {
+                    center,
+                    radius: 0.2,
+                    material,
+                })));
+            }
+        }
+    }
+    world.push(Arc::new(Box::new(Sphere {
+        center: Vec3::new(0.0, 1.0, 0.0),
+        radius: 1.0,
+        material: Arc::new(Box::new(Dielectric {
+            refraction_idx: 1.5,
+        })),
+    })));
+    world.push(Arc::new(Box::new(Sphere {
+        center: Vec3::new(-4.0, 1.0, 0.0),
+        radius: 1.0,
+        material: Arc::new(Box::new(Lambertian::new(Vec3::new(0.4, 0.2, 0.1)))),
+    })));
+    world.push(Arc::new(Box::new(Sphere {
+        center: Vec3::new(4.0, 1.0, 0.0),
+        radius: 1.0,
+        material: Arc::new(Box::new(Metal::new(Vec3::new(0.7, 0.6, 0.5), 0.0))),
+    })));
+
+    World::new(world, 0.0, 0.0)
+}
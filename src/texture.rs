@@ -0,0 +1,259 @@
+use crate::vec3::Vec3;
+
+use image::ImageError;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::path::Path;
+use std::sync::Arc;
+
+/// A colour that varies over a surface.
+pub trait Texture {
+    /// Colour at the surface coordinates `(u, v)` of the hit at `point`.
+    fn value(&self, u: f32, v: f32, point: Vec3) -> Vec3;
+}
+
+pub type TextureHandle = Box<dyn Texture + Send + Sync>;
+
+pub struct ConstantTexture {
+    pub color: Vec3,
+}
+
+impl ConstantTexture {
+    pub fn handle(color: Vec3) -> Arc<TextureHandle> {
+        Arc::new(Box::new(ConstantTexture { color }))
+    }
+}
+
+impl Texture for ConstantTexture {
+    fn value(&self, _u: f32, _v: f32, _point: Vec3) -> Vec3 {
+        self.color
+    }
+}
+
+/// Solid checkerboard of cubes with sides of `scale`, so it doesn't need UVs.
+pub struct CheckerTexture {
+    pub odd: Arc<TextureHandle>,
+    pub even: Arc<TextureHandle>,
+    pub scale: f32,
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f32, v: f32, point: Vec3) -> Vec3 {
+        let cell = (point.x / self.scale).floor()
+            + (point.y / self.scale).floor()
+            + (point.z / self.scale).floor();
+        if cell.rem_euclid(2.0) == 0.0 {
+            self.even.value(u, v, point)
+        } else {
+            self.odd.value(u, v, point)
+        }
+    }
+}
+
+/// What an `ImageTexture` shows outside of `[0, 1]`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum WrapMode {
+    #[default]
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl WrapMode {
+    fn wrap(self, i: i64, n: i64) -> usize {
+        let i = match self {
+            WrapMode::Repeat => i.rem_euclid(n),
+            WrapMode::Clamp => i.clamp(0, n - 1),
+            WrapMode::Mirror => {
+                let i = i.rem_euclid(2 * n);
+                if i < n {
+                    i
+                } else {
+                    2 * n - 1 - i
+                }
+            }
+        };
+        i as usize
+    }
+}
+
+/// Bilinearly filtered image with `v = 0` at the bottom row, as in OBJ files.
+pub struct ImageTexture {
+    width: u32,
+    height: u32,
+    /// Linear RGB, rows from top to bottom
+    pixels: Vec<Vec3>,
+    pub wrap: WrapMode,
+}
+
+impl ImageTexture {
+    /// Reads any format supported by the `image` crate, 8-bit images are
+    /// assumed to be sRGB encoded.
+    pub fn open<P: AsRef<Path>>(path: P, wrap: WrapMode) -> Result<Self, ImageError> {
+        let image = image::open(path)?.to_rgb8();
+        let pixels = image
+            .pixels()
+            .map(|p| {
+                Vec3::new(
+                    srgb_to_linear(p[0]),
+                    srgb_to_linear(p[1]),
+                    srgb_to_linear(p[2]),
+                )
+            })
+            .collect();
+
+        Ok(ImageTexture::from_pixels(
+            image.width(),
+            image.height(),
+            pixels,
+            wrap,
+        ))
+    }
+
+    pub fn from_pixels(width: u32, height: u32, pixels: Vec<Vec3>, wrap: WrapMode) -> Self {
+        assert!(width > 0 && height > 0, "texture must not be empty");
+        assert_eq!(pixels.len(), (width * height) as usize);
+
+        ImageTexture {
+            width,
+            height,
+            pixels,
+            wrap,
+        }
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Vec3 {
+        let x = self.wrap.wrap(x, self.width as i64);
+        let y = self.wrap.wrap(y, self.height as i64);
+        self.pixels[y * self.width as usize + x]
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f32, v: f32, _point: Vec3) -> Vec3 {
+        // Texel centres are at half-integer coordinates
+        let x = u * self.width as f32 - 0.5;
+        let y = (1.0 - v) * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = (1.0 - fx) * self.texel(x0, y0) + fx * self.texel(x0 + 1, y0);
+        let bottom = (1.0 - fx) * self.texel(x0, y0 + 1) + fx * self.texel(x0 + 1, y0 + 1);
+        (1.0 - fy) * top + fy * bottom
+    }
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let c = value as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+const PERLIN_POINT_COUNT: usize = 256;
+
+/// Gradient noise on a lattice of random unit vectors, the same seed always
+/// gives the same noise.
+pub struct Perlin {
+    gradients: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let gradients = (0..PERLIN_POINT_COUNT)
+            .map(|_| {
+                Vec3::new(
+                    rng.gen_range(-1.0..1.0),
+                    rng.gen_range(-1.0..1.0),
+                    rng.gen_range(-1.0..1.0),
+                )
+                .unit()
+            })
+            .collect();
+
+        let permutation = |rng: &mut StdRng| {
+            let mut perm: Vec<usize> = (0..PERLIN_POINT_COUNT).collect();
+            perm.shuffle(rng);
+            perm
+        };
+        let perm_x = permutation(&mut rng);
+        let perm_y = permutation(&mut rng);
+        let perm_z = permutation(&mut rng);
+
+        Perlin {
+            gradients,
+            perm_x,
+            perm_y,
+            perm_z,
+        }
+    }
+
+    /// Smooth noise roughly in `[-1, 1]`.
+    pub fn noise(&self, p: Vec3) -> f32 {
+        let (fx, fy, fz) = (p.x.floor(), p.y.floor(), p.z.floor());
+        let (u, v, w) = (p.x - fx, p.y - fy, p.z - fz);
+        let (i, j, k) = (fx as i64, fy as i64, fz as i64);
+
+        // Hermite smoothing hides the lattice
+        let (uu, vv, ww) = (
+            u * u * (3.0 - 2.0 * u),
+            v * v * (3.0 - 2.0 * v),
+            w * w * (3.0 - 2.0 * w),
+        );
+
+        let mask = PERLIN_POINT_COUNT as i64 - 1;
+        let mut sum = 0.0;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let index = self.perm_x[((i + di) & mask) as usize]
+                        ^ self.perm_y[((j + dj) & mask) as usize]
+                        ^ self.perm_z[((k + dk) & mask) as usize];
+                    let (a, b, c) = (di as f32, dj as f32, dk as f32);
+                    let weight = Vec3::new(u - a, v - b, w - c);
+                    sum += (a * uu + (1.0 - a) * (1.0 - uu))
+                        * (b * vv + (1.0 - b) * (1.0 - vv))
+                        * (c * ww + (1.0 - c) * (1.0 - ww))
+                        * Vec3::dot(self.gradients[index], weight);
+                }
+            }
+        }
+        sum
+    }
+
+    /// Sum of `depth` octaves of noise with halving amplitudes.
+    pub fn turbulence(&self, p: Vec3, depth: u32) -> f32 {
+        let mut sum = 0.0;
+        let mut p = p;
+        let mut weight = 1.0;
+        for _ in 0..depth {
+            sum += weight * self.noise(p);
+            weight *= 0.5;
+            p *= 2.0;
+        }
+        sum.abs()
+    }
+}
+
+/// Marble-like veins of `color` along the z axis, perturbed by Perlin turbulence.
+pub struct NoiseTexture {
+    pub perlin: Perlin,
+    pub color: Vec3,
+    /// Frequency of the veins
+    pub scale: f32,
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f32, _v: f32, point: Vec3) -> Vec3 {
+        let phase = self.scale * point.z + 10.0 * self.perlin.turbulence(point, 7);
+        self.color * 0.5 * (1.0 + phase.sin())
+    }
+}
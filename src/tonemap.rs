@@ -0,0 +1,303 @@
+use crate::transform::Mat3;
+use crate::vec3::Vec3;
+
+use std::str::FromStr;
+
+/// Colour temperatures covered by the Planckian locus approximation, in Kelvin.
+pub const MIN_WHITE_BALANCE: f32 = 1667.0;
+pub const MAX_WHITE_BALANCE: f32 = 25000.0;
+
+/// Curves squeezing the radiance into the displayable `[0, 1]`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum ToneMapOperator {
+    /// Cuts everything above 1 off
+    #[default]
+    Clamp,
+    /// `L / (1 + L)` on the luminance, never reaches white
+    Reinhard,
+    /// Reinhard reaching white at the white point
+    ReinhardExtended,
+    /// Stephen Hill's fit of the ACES reference and sRGB output transforms
+    Aces,
+    /// Troy Sobotka's AgX, as approximated by Benjamin Wrensch, desaturates
+    /// bright colours instead of skewing their hue
+    Agx,
+}
+
+impl FromStr for ToneMapOperator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clamp" => Ok(ToneMapOperator::Clamp),
+            "reinhard" => Ok(ToneMapOperator::Reinhard),
+            "reinhard_extended" => Ok(ToneMapOperator::ReinhardExtended),
+            "aces" => Ok(ToneMapOperator::Aces),
+            "agx" => Ok(ToneMapOperator::Agx),
+            _ => Err(format!("unknown tone mapping operator `{}`", s)),
+        }
+    }
+}
+
+/// Turns linear radiance into display colours: exposure, white balance, the
+/// tone curve and the sRGB transfer function, in that order.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ToneMap {
+    exposure: f32,
+    white_balance: Option<f32>,
+    operator: ToneMapOperator,
+    white_point: f32,
+    /// Exposure and white balance together, linear sRGB to linear sRGB
+    transform: Mat3,
+}
+
+impl Default for ToneMap {
+    /// Clamping without any exposure or white balance.
+    fn default() -> Self {
+        ToneMap {
+            exposure: 0.0,
+            white_balance: None,
+            operator: ToneMapOperator::Clamp,
+            white_point: 4.0,
+            transform: Mat3::identity(),
+        }
+    }
+}
+
+impl ToneMap {
+    /// Scales the radiance by `2^stops`.
+    pub fn with_exposure(mut self, stops: f32) -> Self {
+        self.exposure = stops;
+        self.update_transform();
+        self
+    }
+
+    /// Makes light of the colour temperature `kelvin` white, e.g. 3200 for
+    /// tungsten lamps. The temperature is clamped to the range the Planckian
+    /// locus approximation covers.
+    pub fn with_white_balance(mut self, kelvin: f32) -> Self {
+        self.white_balance = Some(kelvin.clamp(MIN_WHITE_BALANCE, MAX_WHITE_BALANCE));
+        self.update_transform();
+        self
+    }
+
+    pub fn with_operator(mut self, operator: ToneMapOperator) -> Self {
+        self.operator = operator;
+        self
+    }
+
+    /// Luminance that `ReinhardExtended` maps to white.
+    pub fn with_white_point(mut self, white_point: f32) -> Self {
+        self.white_point = white_point.max(f32::MIN_POSITIVE);
+        self
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    pub fn white_balance(&self) -> Option<f32> {
+        self.white_balance
+    }
+
+    pub fn operator(&self) -> ToneMapOperator {
+        self.operator
+    }
+
+    pub fn white_point(&self) -> f32 {
+        self.white_point
+    }
+
+    fn update_transform(&mut self) {
+        let balance = match self.white_balance {
+            Some(kelvin) => white_balance_matrix(kelvin),
+            None => Mat3::identity(),
+        };
+        self.transform = diagonal(Vec3::one() * self.exposure.exp2()) * balance;
+    }
+
+    /// Linear display colour in `[0, 1]`.
+    pub fn map(&self, radiance: Vec3) -> Vec3 {
+        let c = max(self.transform * radiance, 0.0);
+
+        let mapped = match self.operator {
+            ToneMapOperator::Clamp => c,
+            ToneMapOperator::Reinhard => scale_luminance(c, |l| l / (1.0 + l)),
+            ToneMapOperator::ReinhardExtended => {
+                let white2 = self.white_point * self.white_point;
+                scale_luminance(c, |l| l * (1.0 + l / white2) / (1.0 + l))
+            }
+            ToneMapOperator::Aces => aces_fitted(c),
+            ToneMapOperator::Agx => agx(c),
+        };
+
+        Vec3::new(
+            mapped.x.clamp(0.0, 1.0),
+            mapped.y.clamp(0.0, 1.0),
+            mapped.z.clamp(0.0, 1.0),
+        )
+    }
+
+    /// 8-bit sRGB encoded display colour.
+    pub fn to_srgb8(&self, radiance: Vec3) -> [u8; 3] {
+        let c = self.map(radiance);
+        let encode = |v: f32| (linear_to_srgb(v) * 255.0).round() as u8;
+        [encode(c.x), encode(c.y), encode(c.z)]
+    }
+}
+
+/// The sRGB opto-electronic transfer function for values in `[0, 1]`.
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        12.92 * value
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn diagonal(d: Vec3) -> Mat3 {
+    Mat3 {
+        m: [[d.x, 0.0, 0.0], [0.0, d.y, 0.0], [0.0, 0.0, d.z]],
+    }
+}
+
+fn max(c: Vec3, min: f32) -> Vec3 {
+    Vec3::new(c.x.max(min), c.y.max(min), c.z.max(min))
+}
+
+/// Applies `curve` to the luminance and keeps the ratios of the channels.
+fn scale_luminance(c: Vec3, curve: impl Fn(f32) -> f32) -> Vec3 {
+    let l = c.luminance();
+    if l <= 0.0 {
+        return Vec3::zero();
+    }
+    c * (curve(l) / l)
+}
+
+/// Linear sRGB to CIE XYZ, both relative to D65.
+const SRGB_TO_XYZ: Mat3 = Mat3 {
+    m: [
+        [0.412_456_4, 0.357_576_1, 0.180_437_5],
+        [0.212_672_9, 0.715_152_2, 0.072_175_0],
+        [0.019_333_9, 0.119_192, 0.950_304_1],
+    ],
+};
+
+/// XYZ to the cone response space of the Bradford chromatic adaptation.
+const BRADFORD: Mat3 = Mat3 {
+    m: [
+        [0.8951, 0.2664, -0.1614],
+        [-0.7502, 1.7135, 0.0367],
+        [0.0389, -0.0685, 1.0296],
+    ],
+};
+
+/// Colour temperature of the sRGB white point, left alone by the white balance.
+const NEUTRAL_WHITE_BALANCE: f32 = 6504.0;
+
+/// Adapts colours lit by a black body at `kelvin` to look as if they were lit
+/// by one at the temperature of the sRGB white. D65 itself is slightly off the
+/// black body curve, so adapting to it would tint everything.
+fn white_balance_matrix(kelvin: f32) -> Mat3 {
+    let white_xyz = |kelvin| {
+        let (x, y) = planckian_xy(kelvin);
+        Vec3::new(x / y, 1.0, (1.0 - x - y) / y)
+    };
+
+    let source_cone = BRADFORD * white_xyz(kelvin);
+    let target_cone = BRADFORD * white_xyz(NEUTRAL_WHITE_BALANCE);
+    let scale = diagonal(Vec3::new(
+        target_cone.x / source_cone.x,
+        target_cone.y / source_cone.y,
+        target_cone.z / source_cone.z,
+    ));
+
+    let xyz_to_srgb = SRGB_TO_XYZ.inverse().unwrap();
+    let bradford_inverse = BRADFORD.inverse().unwrap();
+    xyz_to_srgb * bradford_inverse * scale * BRADFORD * SRGB_TO_XYZ
+}
+
+/// Chromaticity of a black body between 1667 K and 25000 K, after Kim et al.
+fn planckian_xy(kelvin: f32) -> (f32, f32) {
+    let t = kelvin as f64;
+    let (t2, t3) = (t * t, t * t * t);
+
+    let x = if t <= 4000.0 {
+        -0.266_123_9e9 / t3 - 0.234_358_9e6 / t2 + 0.877_695_6e3 / t + 0.179_910
+    } else {
+        -3.025_846_9e9 / t3 + 2.107_037_9e6 / t2 + 0.222_634_7e3 / t + 0.240_390
+    };
+    let (x2, x3) = (x * x, x * x * x);
+    let y = if t <= 2222.0 {
+        -1.106_381_4 * x3 - 1.348_110_20 * x2 + 2.185_558_32 * x - 0.202_196_83
+    } else if t <= 4000.0 {
+        -0.954_947_6 * x3 - 1.374_185_93 * x2 + 2.091_370_15 * x - 0.167_488_67
+    } else {
+        3.081_758_0 * x3 - 5.873_386_70 * x2 + 3.751_129_97 * x - 0.370_014_83
+    };
+
+    (x as f32, y as f32)
+}
+
+const ACES_INPUT: Mat3 = Mat3 {
+    m: [
+        [0.59719, 0.35458, 0.04823],
+        [0.07600, 0.90834, 0.01566],
+        [0.02840, 0.13383, 0.83777],
+    ],
+};
+
+const ACES_OUTPUT: Mat3 = Mat3 {
+    m: [
+        [1.60475, -0.53108, -0.07367],
+        [-0.10208, 1.10813, -0.00605],
+        [-0.00327, -0.07276, 1.07602],
+    ],
+};
+
+fn aces_fitted(c: Vec3) -> Vec3 {
+    let rrt_and_odt = |v: f32| {
+        let a = v * (v + 0.024_578_6) - 0.000_090_537;
+        let b = v * (0.983_729 * v + 0.432_951) + 0.238_081;
+        a / b
+    };
+
+    let c = ACES_INPUT * c;
+    ACES_OUTPUT * Vec3::new(rrt_and_odt(c.x), rrt_and_odt(c.y), rrt_and_odt(c.z))
+}
+
+const AGX_INSET: Mat3 = Mat3 {
+    m: [
+        [0.842_479_06, 0.078_433_6, 0.079_223_745],
+        [0.042_328_242, 0.878_468_6, 0.079_166_13],
+        [0.042_375_655, 0.078_433_6, 0.879_143],
+    ],
+};
+
+const AGX_OUTSET: Mat3 = Mat3 {
+    m: [
+        [1.196_879, -0.098_020_88, -0.099_029_74],
+        [-0.052_896_85, 1.151_903_1, -0.098_961_18],
+        [-0.052_971_635, -0.098_043_45, 1.151_073_7],
+    ],
+};
+
+fn agx(c: Vec3) -> Vec3 {
+    const MIN_EV: f32 = -12.473_93;
+    const MAX_EV: f32 = 4.026_069;
+
+    // Polynomial fit of the AgX base contrast curve over the log encoded range
+    let contrast = |v: f32| {
+        let x =
+            (v.max(f32::MIN_POSITIVE).log2().clamp(MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV);
+        let (x2, x4) = (x * x, x * x * x * x);
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.002_32
+    };
+
+    let c = AGX_INSET * c;
+    let c = AGX_OUTSET * Vec3::new(contrast(c.x), contrast(c.y), contrast(c.z));
+    // The curve produces display encoded values for a 2.2 gamma display
+    let decode = |v: f32| v.max(0.0).powf(2.2);
+    Vec3::new(decode(c.x), decode(c.y), decode(c.z))
+}
@@ -1,84 +1,276 @@
 use std::convert::TryInto;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Mutex;
 
 use rayon::prelude::*;
 
 use crate::camera::Camera;
-use crate::hit::HitableHandle;
+use crate::film::Film;
+use crate::sampler::{SamplerHandle, SobolSampler};
+use crate::tonemap::ToneMap;
+use crate::vec3::Vec3;
+use crate::world::World;
 
 pub struct Tracer {
     pub(crate) width: u32,
     pub(crate) height: u32,
     pub(crate) block_size: u32,
-    buffer: Mutex<Vec<u8>>,
-    pub(crate) world: HitableHandle,
+    film: Mutex<Film>,
+    pub(crate) world: World,
     pub(crate) camera: Camera,
+    pub(crate) seed: u64,
+    /// Cloned by every render thread
+    pub(crate) sampler: SamplerHandle,
+    tone_map: ToneMap,
+}
+
+/// When `Tracer::render_adaptive` stops sampling a block.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AdaptiveOptions {
+    /// Largest standard error of a pixel's displayed brightness, in `[0, 1]`,
+    /// measured after the exposure of the tone map
+    pub threshold: f32,
+    /// Samples per pixel at which a block is left alone even if it's still noisy
+    pub max_samples: u32,
+}
+
+/// Passed to the progress callback of `Tracer::render_with_progress` after every block.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Progress {
+    pub blocks_done: u32,
+    pub blocks_total: u32,
 }
 
 impl Tracer {
-    pub fn new(
-        width: u32,
-        height: u32,
-        block_size: u32,
-        world: HitableHandle,
-        camera: Camera,
-    ) -> Self {
+    pub fn new(width: u32, height: u32, block_size: u32, world: World, camera: Camera) -> Self {
         assert_eq!(width % block_size, 0);
         assert_eq!(height % block_size, 0);
 
-        let buffer = Mutex::new(vec![0; (width * height * 4).try_into().unwrap()]);
         Tracer {
             width,
             height,
             block_size,
-            buffer,
+            film: Mutex::new(Film::new(width, height)),
             world,
             camera,
+            seed: 0,
+            sampler: Box::new(SobolSampler::default()),
+            tone_map: ToneMap::default(),
         }
     }
 
+    /// Replaces the default Sobol sampler.
+    pub fn with_sampler(mut self, sampler: SamplerHandle) -> Self {
+        self.sampler = sampler;
+        self
+    }
+
+    /// Renders with the same seed are identical, whatever the number of threads.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Used by `flush`, so it only changes the 8-bit output.
+    pub fn with_tone_map(mut self, tone_map: ToneMap) -> Self {
+        self.tone_map = tone_map;
+        self
+    }
+
+    pub fn tone_map(&self) -> &ToneMap {
+        &self.tone_map
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn block_size(&self) -> u32 {
+        self.block_size
+    }
+
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    /// Copies the tone mapped average of all samples so far into `frame` as
+    /// sRGB encoded RGBA8, rows from top to bottom.
+    ///
+    /// `frame` must hold exactly `width * height * 4` bytes.
     pub fn flush(&self, frame: &mut [u8]) {
-        let buffer = self.buffer.lock().unwrap();
+        let film = self.film.lock().unwrap();
 
-        frame.copy_from_slice(&buffer);
+        film.to_rgba8(frame, &self.tone_map);
+    }
+
+    /// Average linear radiance of every pixel, rows from top to bottom.
+    pub fn radiance(&self) -> Vec<Vec3> {
+        let film = self.film.lock().unwrap();
+
+        (0..(self.width * self.height) as usize)
+            .map(|idx| film.average(idx))
+            .collect()
+    }
+
+    /// Number of samples of every pixel, rows from top to bottom.
+    pub fn sample_counts(&self) -> Vec<u32> {
+        let film = self.film.lock().unwrap();
+
+        (0..(self.width * self.height) as usize)
+            .map(|idx| film.sample_count(idx))
+            .collect()
+    }
+
+    /// Runs `f` with the accumulated samples.
+    pub fn with_film<R>(&self, f: impl FnOnce(&Film) -> R) -> R {
+        f(&self.film.lock().unwrap())
+    }
+
+    /// Throws away all the samples taken so far.
+    pub fn reset(&self) {
+        self.film.lock().unwrap().clear();
     }
 
-    #[allow(clippy::suspicious_operation_groupings)]
     pub fn render(&self, steps: u32) {
-        let block_count = (self.width * self.height) / (self.block_size * self.block_size);
+        self.render_with_progress(steps, |_| {})
+    }
+
+    /// Adds `steps` samples to every pixel, calling `progress` from the render
+    /// threads whenever a block is finished.
+    pub fn render_with_progress<F>(&self, steps: u32, progress: F)
+    where
+        F: Fn(Progress) + Sync,
+    {
+        let blocks: Vec<u32> = (0..self.block_count()).collect();
+        self.render_blocks(&blocks, steps, &progress);
+    }
+
+    /// Keeps adding up to `steps` samples to the blocks that still have a pixel
+    /// with an error above `options.threshold`, until every block converges or
+    /// reaches `options.max_samples`. Each pass calls `progress` after every
+    /// block, counting only the blocks of that pass.
+    ///
+    /// Pixels need a couple of samples from `render` first, blocks without
+    /// them are treated as unconverged.
+    pub fn render_adaptive<F>(&self, steps: u32, options: &AdaptiveOptions, progress: F)
+    where
+        F: Fn(Progress) + Sync,
+    {
+        assert!(steps > 0);
+        let exposure = self.tone_map.exposure().exp2();
+
+        loop {
+            // Every pixel of a block has the same number of samples
+            let (blocks, samples): (Vec<u32>, Vec<u32>) = {
+                let film = self.film.lock().unwrap();
+
+                (0..self.block_count())
+                    .map(|block_idx| {
+                        let pixels = self.block_pixels(block_idx);
+                        (
+                            block_idx,
+                            pixels
+                                .clone()
+                                .map(|idx| film.sample_count(idx))
+                                .min()
+                                .unwrap(),
+                            pixels,
+                        )
+                    })
+                    .filter(|(_, samples, pixels)| {
+                        *samples < options.max_samples
+                            && pixels
+                                .clone()
+                                .any(|idx| film.error(idx, exposure) > options.threshold)
+                    })
+                    .map(|(block_idx, samples, _)| (block_idx, samples))
+                    .unzip()
+            };
+
+            match samples.iter().min() {
+                Some(fewest) => {
+                    let steps = steps.min(options.max_samples - fewest);
+                    self.render_blocks(&blocks, steps, &progress);
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn block_count(&self) -> u32 {
+        (self.width * self.height) / (self.block_size * self.block_size)
+    }
+
+    /// Film index of the first pixel of every line of the block, from the top.
+    #[allow(clippy::suspicious_operation_groupings)]
+    fn block_lines(&self, block_idx: u32) -> impl Iterator<Item = u32> + Clone + '_ {
         let block_count_in_line = self.width / self.block_size;
 
-        (0..block_count).into_par_iter().for_each(|block_idx| {
-            for line_idx in 0..self.block_size {
-                let mut line_buffer = vec![0xffu8; (self.block_size * 4).try_into().unwrap()];
+        (0..self.block_size).map(move |line_idx| {
+            (block_idx % block_count_in_line) * self.block_size
+                + line_idx * self.width
+                + (block_idx / block_count_in_line) * self.width * self.block_size
+        })
+    }
 
-                let line_pos = (block_idx % block_count_in_line) * self.block_size
-                    + line_idx * self.width
-                    + (block_idx / block_count_in_line) * self.width * self.block_size;
+    fn block_pixels(&self, block_idx: u32) -> impl Iterator<Item = usize> + Clone + '_ {
+        self.block_lines(block_idx).flat_map(move |line_pos| {
+            (line_pos..line_pos + self.block_size).map(|idx| idx as usize)
+        })
+    }
+
+    fn render_blocks<F>(&self, blocks: &[u32], steps: u32, progress: &F)
+    where
+        F: Fn(Progress) + Sync,
+    {
+        let blocks_done = AtomicU32::new(0);
+
+        blocks.par_iter().for_each(|&block_idx| {
+            for line_pos in self.block_lines(block_idx) {
+                let mut line_buffer = vec![Vec3::zero(); self.block_size.try_into().unwrap()];
+                let mut square_buffer = vec![0.0; self.block_size.try_into().unwrap()];
+
+                // The samples taken so far number the new ones
+                let first_samples: Vec<u32> = {
+                    let film = self.film.lock().unwrap();
+
+                    (line_pos..line_pos + self.block_size)
+                        .map(|idx| film.sample_count(idx as usize))
+                        .collect()
+                };
 
                 for i in 0..self.block_size {
                     let x = (line_pos + i) % self.width;
                     let y = self.height - ((line_pos + i) / self.width);
 
-                    let color = self.trace(x, y, steps);
-
-                    let rgba = [
-                        (color.x * 255.99) as u8,
-                        (color.y * 255.99) as u8,
-                        (color.z * 255.99) as u8,
-                        0xff,
-                    ];
-
-                    line_buffer[(i * 4) as usize..((i + 1) * 4) as usize].copy_from_slice(&rgba);
+                    let (color, squares) = self.trace(x, y, first_samples[i as usize], steps);
+                    line_buffer[i as usize] = color;
+                    square_buffer[i as usize] = squares;
                 }
 
                 {
-                    let mut buffer = self.buffer.lock().unwrap();
+                    let mut film = self.film.lock().unwrap();
 
-                    buffer[(line_pos * 4) as usize..((line_pos + self.block_size) * 4) as usize]
-                        .copy_from_slice(&line_buffer);
+                    film.add_samples(line_pos as usize, &line_buffer, &square_buffer, steps);
                 }
             }
+
+            progress(Progress {
+                blocks_done: blocks_done.fetch_add(1, Ordering::Relaxed) + 1,
+                blocks_total: blocks.len() as u32,
+            });
         })
     }
 }
@@ -0,0 +1,666 @@
+use crate::aabb::Aabb;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+
+use std::ops;
+
+/// Row-major 3x3 matrix acting on column vectors.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Mat3 {
+    pub m: [[f32; 3]; 3],
+}
+
+impl Mat3 {
+    pub fn identity() -> Self {
+        Mat3 {
+            m: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+        }
+    }
+
+    /// Matrix with `x`, `y` and `z` as its columns.
+    pub fn from_columns(x: Vec3, y: Vec3, z: Vec3) -> Self {
+        Mat3 {
+            m: [[x.x, y.x, z.x], [x.y, y.y, z.y], [x.z, y.z, z.z]],
+        }
+    }
+
+    pub fn transpose(&self) -> Self {
+        let m = &self.m;
+        Mat3 {
+            m: [
+                [m[0][0], m[1][0], m[2][0]],
+                [m[0][1], m[1][1], m[2][1]],
+                [m[0][2], m[1][2], m[2][2]],
+            ],
+        }
+    }
+
+    pub fn determinant(&self) -> f32 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    /// Inverse through the adjugate, `None` for singular matrices.
+    pub fn inverse(&self) -> Option<Self> {
+        let det = self.determinant();
+        if det.abs() < 1e-12 {
+            return None;
+        }
+
+        let m = &self.m;
+        let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| {
+            (m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]) / det
+        };
+        Some(Mat3 {
+            m: [
+                [
+                    cofactor(1, 2, 1, 2),
+                    -cofactor(0, 2, 1, 2),
+                    cofactor(0, 1, 1, 2),
+                ],
+                [
+                    -cofactor(1, 2, 0, 2),
+                    cofactor(0, 2, 0, 2),
+                    -cofactor(0, 1, 0, 2),
+                ],
+                [
+                    cofactor(1, 2, 0, 1),
+                    -cofactor(0, 2, 0, 1),
+                    cofactor(0, 1, 0, 1),
+                ],
+            ],
+        })
+    }
+}
+
+impl ops::Mul for Mat3 {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        let mut result = [[0.0; 3]; 3];
+        for (i, row) in result.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..3).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Mat3 { m: result }
+    }
+}
+
+impl ops::Mul<Vec3> for Mat3 {
+    type Output = Vec3;
+
+    fn mul(self, v: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+}
+
+/// Row-major 4x4 matrix acting on column vectors.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Mat4 {
+    pub m: [[f32; 4]; 4],
+}
+
+impl Mat4 {
+    pub fn identity() -> Self {
+        Mat4 {
+            m: [
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn translation(offset: Vec3) -> Self {
+        let mut result = Mat4::identity();
+        result.m[0][3] = offset.x;
+        result.m[1][3] = offset.y;
+        result.m[2][3] = offset.z;
+        result
+    }
+
+    pub fn scaling(scale: Vec3) -> Self {
+        let mut result = Mat4::identity();
+        result.m[0][0] = scale.x;
+        result.m[1][1] = scale.y;
+        result.m[2][2] = scale.z;
+        result
+    }
+
+    /// Counter-clockwise rotation by `radians` around `axis`.
+    pub fn rotation(axis: Vec3, radians: f32) -> Self {
+        let a = axis.unit();
+        let (sin, cos) = radians.sin_cos();
+        let t = 1.0 - cos;
+
+        Mat4 {
+            m: [
+                [
+                    t * a.x * a.x + cos,
+                    t * a.x * a.y - sin * a.z,
+                    t * a.x * a.z + sin * a.y,
+                    0.0,
+                ],
+                [
+                    t * a.x * a.y + sin * a.z,
+                    t * a.y * a.y + cos,
+                    t * a.y * a.z - sin * a.x,
+                    0.0,
+                ],
+                [
+                    t * a.x * a.z - sin * a.y,
+                    t * a.y * a.z + sin * a.x,
+                    t * a.z * a.z + cos,
+                    0.0,
+                ],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    /// Linear part `m` with no translation.
+    pub fn from_mat3(m: Mat3) -> Self {
+        let mut result = Mat4::identity();
+        for (i, row) in m.m.iter().enumerate() {
+            result.m[i][..3].copy_from_slice(row);
+        }
+        result
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut result = Mat4::identity();
+        for (i, row) in self.m.iter().enumerate() {
+            for (j, value) in row.iter().enumerate() {
+                result.m[j][i] = *value;
+            }
+        }
+        result
+    }
+
+    /// Gauss-Jordan elimination with partial pivoting, `None` for singular matrices.
+    pub fn inverse(&self) -> Option<Self> {
+        let mut a = self.m;
+        let mut result = Mat4::identity().m;
+
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))
+                .unwrap();
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            result.swap(col, pivot);
+
+            let scale = 1.0 / a[col][col];
+            for j in 0..4 {
+                a[col][j] *= scale;
+                result[col][j] *= scale;
+            }
+
+            for row in 0..4 {
+                if row != col {
+                    let factor = a[row][col];
+                    for j in 0..4 {
+                        a[row][j] -= factor * a[col][j];
+                        result[row][j] -= factor * result[col][j];
+                    }
+                }
+            }
+        }
+
+        Some(Mat4 { m: result })
+    }
+
+    pub fn transform_point(&self, p: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+            m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+            m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3],
+        )
+    }
+
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+
+    /// Box around the eight transformed corners of `bbox`.
+    pub fn transform_aabb(&self, bbox: Aabb) -> Aabb {
+        let mut corners = [Vec3::zero(); 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            *corner = self.transform_point(Vec3::new(
+                if i & 1 == 0 { bbox.min.x } else { bbox.max.x },
+                if i & 2 == 0 { bbox.min.y } else { bbox.max.y },
+                if i & 4 == 0 { bbox.min.z } else { bbox.max.z },
+            ));
+        }
+        Aabb::from_points(&corners)
+    }
+}
+
+impl ops::Mul for Mat4 {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        let mut result = [[0.0; 4]; 4];
+        for (i, row) in result.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Mat4 { m: result }
+    }
+}
+
+/// An affine transform along with its inverse, so that neither has to be
+/// recomputed when transforming normals or going back to object space.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Transform {
+    matrix: Mat4,
+    inverse: Mat4,
+}
+
+impl Transform {
+    /// `None` if `matrix` is not invertible.
+    pub fn new(matrix: Mat4) -> Option<Self> {
+        let inverse = matrix.inverse()?;
+        Some(Transform { matrix, inverse })
+    }
+
+    pub fn identity() -> Self {
+        Transform {
+            matrix: Mat4::identity(),
+            inverse: Mat4::identity(),
+        }
+    }
+
+    pub fn translation(offset: Vec3) -> Self {
+        Transform {
+            matrix: Mat4::translation(offset),
+            inverse: Mat4::translation(-offset),
+        }
+    }
+
+    /// Scales by zero produce an infinite inverse.
+    pub fn scaling(scale: Vec3) -> Self {
+        Transform {
+            matrix: Mat4::scaling(scale),
+            inverse: Mat4::scaling(Vec3::one() / scale),
+        }
+    }
+
+    pub fn rotation(axis: Vec3, radians: f32) -> Self {
+        let matrix = Mat4::rotation(axis, radians);
+        Transform {
+            matrix,
+            inverse: matrix.transpose(),
+        }
+    }
+
+    pub fn from_quat(q: Quat) -> Self {
+        let matrix = Mat4::from_mat3(q.to_mat3());
+        Transform {
+            matrix,
+            inverse: matrix.transpose(),
+        }
+    }
+
+    pub fn matrix(&self) -> &Mat4 {
+        &self.matrix
+    }
+
+    pub fn inverse_matrix(&self) -> &Mat4 {
+        &self.inverse
+    }
+
+    pub fn inverse(&self) -> Self {
+        Transform {
+            matrix: self.inverse,
+            inverse: self.matrix,
+        }
+    }
+
+    pub fn point(&self, p: Vec3) -> Vec3 {
+        self.matrix.transform_point(p)
+    }
+
+    pub fn vector(&self, v: Vec3) -> Vec3 {
+        self.matrix.transform_vector(v)
+    }
+
+    /// Normals transform with the inverse transpose, the result is not normalized.
+    pub fn normal(&self, n: Vec3) -> Vec3 {
+        let m = &self.inverse.m;
+        Vec3::new(
+            m[0][0] * n.x + m[1][0] * n.y + m[2][0] * n.z,
+            m[0][1] * n.x + m[1][1] * n.y + m[2][1] * n.z,
+            m[0][2] * n.x + m[1][2] * n.y + m[2][2] * n.z,
+        )
+    }
+
+    /// The direction is left unnormalized so that `t` along both rays is the same.
+    pub fn ray(&self, ray: &Ray) -> Ray {
+        Ray {
+            origin: self.point(ray.origin),
+            direction: self.vector(ray.direction),
+            time: ray.time,
+        }
+    }
+
+    pub fn aabb(&self, bbox: Aabb) -> Aabb {
+        self.matrix.transform_aabb(bbox)
+    }
+}
+
+impl ops::Mul for Transform {
+    type Output = Self;
+
+    /// Applies `other` first, then `self`.
+    fn mul(self, other: Self) -> Self {
+        Transform {
+            matrix: self.matrix * other.matrix,
+            inverse: other.inverse * self.inverse,
+        }
+    }
+}
+
+/// Scale, then rotation, then translation. Unlike a matrix it can be
+/// interpolated without shearing.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Trs {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Trs {
+    pub fn identity() -> Self {
+        Trs {
+            translation: Vec3::zero(),
+            rotation: Quat::identity(),
+            scale: Vec3::one(),
+        }
+    }
+
+    pub fn lerp(a: &Trs, b: &Trs, t: f32) -> Trs {
+        Trs {
+            translation: a.translation + t * (b.translation - a.translation),
+            rotation: Quat::slerp(a.rotation, b.rotation, t),
+            scale: a.scale + t * (b.scale - a.scale),
+        }
+    }
+
+    pub fn to_transform(&self) -> Transform {
+        Transform::translation(self.translation)
+            * Transform::from_quat(self.rotation)
+            * Transform::scaling(self.scale)
+    }
+}
+
+/// A transform moving from `start` at `time0` to `end` at `time1`, it stays
+/// put outside of that interval.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AnimatedTransform {
+    pub start: Trs,
+    pub end: Trs,
+    pub time0: f32,
+    pub time1: f32,
+}
+
+impl AnimatedTransform {
+    /// Number of instants the motion is sampled at to bound it.
+    const BOUND_STEPS: u32 = 64;
+
+    pub fn at(&self, time: f32) -> Transform {
+        Trs::lerp(&self.start, &self.end, self.progress(time)).to_transform()
+    }
+
+    /// How far along the motion `time` is, from 0 to 1.
+    fn progress(&self, time: f32) -> f32 {
+        if self.time1 <= self.time0 {
+            return 0.0;
+        }
+        ((time - self.time0) / (self.time1 - self.time0)).clamp(0.0, 1.0)
+    }
+
+    /// Box around `bbox` over the whole motion between `t0` and `t1`.
+    ///
+    /// Translation and scale move every point in a straight line, so the boxes
+    /// at evenly spaced instants cover them between the instants. The rotation
+    /// turns points along arcs instead, so each pair of neighbouring boxes is
+    /// grown by the farthest an arc of that step strays from its chord.
+    pub fn aabb(&self, bbox: Aabb, t0: f32, t1: f32) -> Aabb {
+        let steps = AnimatedTransform::BOUND_STEPS;
+        let time = |i: u32| t0 + (t1 - t0) * i as f32 / steps as f32;
+
+        // Bounds on the distance of a scaled point from the centre of rotation,
+        // and on how much the scaling moves it over the whole motion
+        let corner = Vec3::new(
+            bbox.min.x.abs().max(bbox.max.x.abs()),
+            bbox.min.y.abs().max(bbox.max.y.abs()),
+            bbox.min.z.abs().max(bbox.max.z.abs()),
+        );
+        let scale = Vec3::new(
+            self.start.scale.x.abs().max(self.end.scale.x.abs()),
+            self.start.scale.y.abs().max(self.end.scale.y.abs()),
+            self.start.scale.z.abs().max(self.end.scale.z.abs()),
+        );
+        let radius = (scale * corner).length();
+        let scale_change = ((self.end.scale - self.start.scale) * corner).length();
+        let angle = 2.0
+            * Quat::dot(self.start.rotation, self.end.rotation)
+                .abs()
+                .min(1.0)
+                .acos();
+
+        let mut previous = self.at(time(0)).aabb(bbox);
+        let mut result = previous;
+        for i in 1..steps + 1 {
+            let current = self.at(time(i)).aabb(bbox);
+
+            let progress = self.progress(time(i)) - self.progress(time(i - 1));
+            let step_angle = angle * progress;
+            // The sagitta r (1 - cos(a / 2)), plus the scaling during the turn
+            let sagitta = 2.0 * radius * (step_angle / 4.0).sin().powi(2);
+            let padding = sagitta + step_angle * scale_change * progress;
+
+            result = Aabb::merge(result, Aabb::merge(previous, current).expanded(padding));
+            previous = current;
+        }
+        result
+    }
+}
+
+/// Rotation quaternion `w + xi + yj + zk`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Quat {
+    pub w: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Quat {
+    pub fn identity() -> Self {
+        Quat {
+            w: 1.0,
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        }
+    }
+
+    /// Counter-clockwise rotation by `radians` around `axis`.
+    pub fn from_axis_angle(axis: Vec3, radians: f32) -> Self {
+        let a = axis.unit();
+        let (sin, cos) = (radians / 2.0).sin_cos();
+        Quat {
+            w: cos,
+            x: a.x * sin,
+            y: a.y * sin,
+            z: a.z * sin,
+        }
+    }
+
+    pub fn dot(a: Quat, b: Quat) -> f32 {
+        a.w * b.w + a.x * b.x + a.y * b.y + a.z * b.z
+    }
+
+    pub fn length(self) -> f32 {
+        Quat::dot(self, self).sqrt()
+    }
+
+    pub fn unit(self) -> Self {
+        let inv = 1.0 / self.length();
+        Quat {
+            w: self.w * inv,
+            x: self.x * inv,
+            y: self.y * inv,
+            z: self.z * inv,
+        }
+    }
+
+    pub fn conjugate(self) -> Self {
+        Quat {
+            w: self.w,
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+
+    /// Rotates `v` by this unit quaternion.
+    pub fn rotate(self, v: Vec3) -> Vec3 {
+        let u = Vec3::new(self.x, self.y, self.z);
+        let t = 2.0 * Vec3::cross(u, v);
+        v + self.w * t + Vec3::cross(u, t)
+    }
+
+    /// Spherical interpolation from `a` at `t = 0` to `b` at `t = 1` along the shorter arc.
+    pub fn slerp(a: Quat, b: Quat, t: f32) -> Quat {
+        let mut cos_theta = Quat::dot(a, b);
+        let b = if cos_theta < 0.0 {
+            cos_theta = -cos_theta;
+            Quat {
+                w: -b.w,
+                x: -b.x,
+                y: -b.y,
+                z: -b.z,
+            }
+        } else {
+            b
+        };
+
+        let (wa, wb) = if cos_theta > 0.9995 {
+            // Nearly parallel, a normalized lerp is accurate and avoids dividing by sin(theta)
+            (1.0 - t, t)
+        } else {
+            let theta = cos_theta.acos();
+            let sin_theta = theta.sin();
+            (
+                ((1.0 - t) * theta).sin() / sin_theta,
+                (t * theta).sin() / sin_theta,
+            )
+        };
+
+        Quat {
+            w: wa * a.w + wb * b.w,
+            x: wa * a.x + wb * b.x,
+            y: wa * a.y + wb * b.y,
+            z: wa * a.z + wb * b.z,
+        }
+        .unit()
+    }
+
+    pub fn to_mat3(self) -> Mat3 {
+        let Quat { w, x, y, z } = self;
+        Mat3 {
+            m: [
+                [
+                    1.0 - 2.0 * (y * y + z * z),
+                    2.0 * (x * y - w * z),
+                    2.0 * (x * z + w * y),
+                ],
+                [
+                    2.0 * (x * y + w * z),
+                    1.0 - 2.0 * (x * x + z * z),
+                    2.0 * (y * z - w * x),
+                ],
+                [
+                    2.0 * (x * z - w * y),
+                    2.0 * (y * z + w * x),
+                    1.0 - 2.0 * (x * x + y * y),
+                ],
+            ],
+        }
+    }
+}
+
+impl ops::Mul for Quat {
+    type Output = Self;
+
+    /// Rotation by `other` followed by `self`.
+    fn mul(self, other: Self) -> Self {
+        Quat {
+            w: self.w * other.w - self.x * other.x - self.y * other.y - self.z * other.z,
+            x: self.w * other.x + self.x * other.w + self.y * other.z - self.z * other.y,
+            y: self.w * other.y - self.x * other.z + self.y * other.w + self.z * other.x,
+            z: self.w * other.z + self.x * other.y - self.y * other.x + self.z * other.w,
+        }
+    }
+}
+
+/// Orthonormal basis around `w`, usually a surface normal.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    pub fn from_w(w: Vec3) -> Self {
+        let w = w.unit();
+        let a = if w.x.abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let v = Vec3::cross(w, a).unit();
+        let u = Vec3::cross(w, v);
+        Onb { u, v, w }
+    }
+
+    /// World space direction of the local coordinates `a`.
+    pub fn local(&self, a: Vec3) -> Vec3 {
+        a.x * self.u + a.y * self.v + a.z * self.w
+    }
+
+    /// Local coordinates of the world space direction `d`.
+    pub fn to_local(&self, d: Vec3) -> Vec3 {
+        Vec3::new(
+            Vec3::dot(d, self.u),
+            Vec3::dot(d, self.v),
+            Vec3::dot(d, self.w),
+        )
+    }
+
+    pub fn to_mat3(&self) -> Mat3 {
+        Mat3::from_columns(self.u, self.v, self.w)
+    }
+}
@@ -1,58 +1,27 @@
 use crate::vec3::Vec3;
-use rand::{Rng, SeedableRng};
 
-use core::ops::{Deref, DerefMut};
+use std::f32::consts::PI;
 
-/// A shim that points to the global `rand::rngs::ThreadRng` instance. isn't safe for multi-threading.
-///
-/// This struct is created by [`thread_local()`](../struct.rand::rngs::ThreadRng.html#method.thread_local)
-pub struct ThreadFastRng(*mut rand::rngs::SmallRng);
-
-impl Deref for ThreadFastRng {
-    type Target = rand::rngs::SmallRng;
-
-    fn deref(&self) -> &Self::Target {
-        unsafe { &*self.0 }
-    }
+/// Maps a point of the unit square uniformly onto the unit sphere.
+pub fn uniform_sphere((u, v): (f32, f32)) -> Vec3 {
+    let z = 1.0 - 2.0 * u;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * v;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
 }
 
-impl DerefMut for ThreadFastRng {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        unsafe { &mut *self.0 }
+/// Maps a point of the unit square uniformly onto the unit disk in the xy
+/// plane, keeping neighbouring points close together.
+pub fn concentric_disk((u, v): (f32, f32)) -> Vec3 {
+    let (x, y) = (2.0 * u - 1.0, 2.0 * v - 1.0);
+    if x == 0.0 && y == 0.0 {
+        return Vec3::zero();
     }
-}
 
-pub trait FromRawPtr<T> {
-    fn from_ptr(ptr: *mut T) -> Self;
-}
-
-impl FromRawPtr<rand::rngs::SmallRng> for ThreadFastRng {
-    fn from_ptr(ptr: *mut rand::rngs::SmallRng) -> ThreadFastRng {
-        ThreadFastRng(ptr)
-    }
-}
-
-pub fn local_rng() -> ThreadFastRng {
-    use std::cell::RefCell;
-    thread_local! {
-        pub static THREAD_FAST_RNG: RefCell<rand::rngs::SmallRng> = RefCell::new(rand::rngs::SmallRng::from_entropy());
-    }
-    let ptr = THREAD_FAST_RNG.with(|r| r.as_ptr());
-    ThreadFastRng::from_ptr(ptr)
-}
-
-pub fn random_in_unit_sphere() -> Vec3 {
-    let mut rng = local_rng();
-    let mut p: Vec3;
-    loop {
-        p = Vec3::new(
-            rng.gen_range(-1.0..=1.0),
-            rng.gen_range(-1.0..=1.0),
-            rng.gen_range(-1.0..=1.0),
-        );
-        if p.squared_length() < 1.0 {
-            break;
-        }
-    }
-    p
+    let (r, theta) = if x.abs() > y.abs() {
+        (x, PI / 4.0 * (y / x))
+    } else {
+        (y, PI / 2.0 - PI / 4.0 * (x / y))
+    };
+    Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
 }
@@ -50,6 +50,12 @@ impl Vec3 {
         self / self.length()
     }
 
+    /// Relative luminance of a linear Rec. 709 colour.
+    #[inline]
+    pub fn luminance(self) -> f32 {
+        0.2126 * self.x + 0.7152 * self.y + 0.0722 * self.z
+    }
+
     #[inline]
     pub fn dot(left: Vec3, right: Vec3) -> f32 {
         left.x * right.x + left.y * right.y + left.z * right.z
@@ -0,0 +1,103 @@
+use crate::cli::Options;
+use crate::{save_screenshot, save_spp_image};
+use pathtracer_rs::{AdaptiveOptions, Tracer};
+
+use log::error;
+use pixels::{Error, Pixels, SurfaceTexture};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use winit::dpi::LogicalSize;
+use winit::event::{Event, VirtualKeyCode};
+use winit::event_loop::{ControlFlow, EventLoop};
+use winit::window::WindowBuilder;
+use winit_input_helper::WinitInputHelper;
+
+const WINDOW_SCALE: u32 = 1;
+const MAX_PASS_SAMPLES: u32 = 64;
+
+/// Opens a window showing the image while it renders in the background.
+pub fn run(
+    tracer: Arc<Tracer>,
+    samples: u32,
+    adaptive: Option<AdaptiveOptions>,
+    output: PathBuf,
+    options: Options,
+) -> Result<(), Error> {
+    let (width, height) = (tracer.width(), tracer.height());
+    let event_loop = EventLoop::new();
+    let mut input = WinitInputHelper::new();
+    let window = {
+        let size = LogicalSize::new(
+            (width * WINDOW_SCALE) as f64,
+            (height * WINDOW_SCALE) as f64,
+        );
+        WindowBuilder::new()
+            .with_title("Pathtracer")
+            .with_inner_size(size)
+            .with_min_inner_size(size)
+            .build(&event_loop)
+            .unwrap()
+    };
+
+    let mut pixels = {
+        let window_size = window.inner_size();
+        let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
+        Pixels::new(width, height, surface_texture)?
+    };
+
+    let tracer_clone = tracer.clone();
+
+    thread::spawn(move || {
+        println!("Started rendering");
+        let start = std::time::Instant::now();
+
+        // Passes grow so that the first ones show up quickly while the later
+        // ones don't spend too much time synchronizing
+        let mut done = 0;
+        while done < samples {
+            let steps = done.clamp(1, MAX_PASS_SAMPLES).min(samples - done);
+            tracer_clone.render(steps);
+            done += steps;
+        }
+        if let Some(adaptive) = adaptive {
+            tracer_clone.render_adaptive(samples.min(MAX_PASS_SAMPLES), &adaptive, |_| {});
+        }
+
+        save_screenshot(&tracer_clone, &output, &options.save);
+        save_spp_image(&tracer_clone, &options);
+        println!("Rendering complete, took {:?}", start.elapsed());
+    });
+
+    event_loop.run(move |event, _, control_flow| {
+        // Draw the current frame
+        if let Event::RedrawRequested(_) = event {
+            tracer.flush(pixels.get_frame());
+            if pixels
+                .render()
+                .map_err(|e| error!("pixels.render() failed: {}", e))
+                .is_err()
+            {
+                *control_flow = ControlFlow::Exit;
+                return;
+            }
+        }
+
+        // Handle input events
+        if input.update(&event) {
+            // Close events
+            if input.key_pressed(VirtualKeyCode::Escape) || input.quit() {
+                *control_flow = ControlFlow::Exit;
+                return;
+            }
+
+            // Resize the window
+            if let Some(size) = input.window_resized() {
+                pixels.resize(size.width, size.height);
+            }
+
+            // Update internal state and request a redraw
+            window.request_redraw();
+        }
+    });
+}
@@ -0,0 +1,122 @@
+use crate::aabb::Aabb;
+use crate::bvh::{BvhOptions, LinearBvh};
+use crate::environment::{Environment, EnvironmentHandle, GradientEnvironment};
+use crate::hit::{HitRecord, Hitable, HitableHandle};
+use crate::light::{LightId, LightSample};
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::vec3::Vec3;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// The scene geometry in a BVH, along with the emissive primitives that can be
+/// sampled directly. The BVH bounds cover the shutter interval from `t0` to
+/// `t1`, primitives without a bounding box are tested next to it. Rays that
+/// miss everything see the environment, a sky gradient unless replaced with
+/// `with_environment`.
+pub struct World {
+    bvh: LinearBvh,
+    lights: Vec<Arc<HitableHandle>>,
+    /// Index of every light in `lights`
+    light_indices: HashMap<LightId, usize>,
+    environment: EnvironmentHandle,
+}
+
+impl World {
+    pub fn new(primitives: Vec<Arc<HitableHandle>>, t0: f32, t1: f32) -> Self {
+        World::with_options(primitives, t0, t1, &BvhOptions::default())
+    }
+
+    pub fn with_options(
+        mut primitives: Vec<Arc<HitableHandle>>,
+        t0: f32,
+        t1: f32,
+        options: &BvhOptions,
+    ) -> Self {
+        let lights: Vec<_> = primitives
+            .iter()
+            .filter(|primitive| primitive.as_light().is_some())
+            .cloned()
+            .collect();
+        let light_indices = lights
+            .iter()
+            .enumerate()
+            .map(|(idx, light)| (LightId::of(light.as_light().unwrap()), idx))
+            .collect();
+
+        World {
+            bvh: LinearBvh::with_options(&mut primitives[..], t0, t1, options),
+            lights,
+            light_indices,
+            environment: Box::new(GradientEnvironment::default()),
+        }
+    }
+
+    pub fn with_environment(mut self, environment: EnvironmentHandle) -> Self {
+        self.environment = environment;
+        self
+    }
+
+    pub fn environment(&self) -> &dyn Environment {
+        self.environment.as_ref()
+    }
+
+    pub fn lights(&self) -> &[Arc<HitableHandle>] {
+        &self.lights
+    }
+
+    /// Number of lights `sample_light` chooses from, including the environment
+    /// if it can be sampled.
+    fn light_count(&self) -> usize {
+        self.lights.len() + self.environment.as_light().is_some() as usize
+    }
+
+    /// Picks one of the lights uniformly and samples it, the pdf accounts for the choice.
+    pub fn sample_light(&self, origin: Vec3, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        let count = self.light_count();
+        if count == 0 {
+            return None;
+        }
+
+        let idx = ((sampler.get_1d() * count as f32) as usize).min(count - 1);
+        let light = match self.lights.get(idx) {
+            Some(light) => light.as_light().unwrap(),
+            None => self.environment.as_light().unwrap(),
+        };
+        let mut sample = light.sample(origin, sampler.get_2d())?;
+        sample.pdf /= count as f32;
+        Some(sample)
+    }
+
+    /// Density of `sample_light` picking `direction` from `origin` and finding
+    /// the light that a ray in that direction hit. Lights behind it are
+    /// blocked, so they can't produce the same sample.
+    pub fn light_pdf(&self, light: Option<LightId>, origin: Vec3, direction: Vec3) -> f32 {
+        match light.and_then(|light| self.light_indices.get(&light)) {
+            Some(&idx) => {
+                let light = self.lights[idx].as_light().unwrap();
+                light.pdf(origin, direction) / self.light_count() as f32
+            }
+            None => 0.0,
+        }
+    }
+
+    /// Density of `sample_light` picking `direction` from the environment.
+    pub fn environment_pdf(&self, direction: Vec3) -> f32 {
+        match self.environment.as_light() {
+            Some(light) => light.pdf(Vec3::zero(), direction) / self.light_count() as f32,
+            None => 0.0,
+        }
+    }
+}
+
+impl Hitable for World {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        self.bvh.hit(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self, t0: f32, t1: f32) -> Option<Aabb> {
+        self.bvh.bounding_box(t0, t1)
+    }
+}